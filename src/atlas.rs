@@ -1,14 +1,28 @@
 extern crate binary_rw;
 mod output;
-use std::{path::{PathBuf, Path}, collections::HashMap, io::Write};
+mod packer;
+mod unpack;
+mod cache;
+mod config_file;
+mod check;
+use std::{path::{PathBuf, Path}, collections::HashMap, io::{BufReader, Write}, hash::{Hash, Hasher}};
 
-use asefile::AsepriteFile;
+use asefile::{AsepriteFile, BlendMode, LayerType};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use indexmap::IndexMap;
 use crunch::{Item, Rotation};
-use image::{RgbaImage, ImageBuffer, GenericImage, GenericImageView, Rgba};
+use image::{RgbaImage, RgbImage, ImageBuffer, GenericImage, GenericImageView, Rgba, AnimationDecoder, DynamicImage};
+use image::codecs::gif::GifDecoder;
+use rayon::prelude::*;
 
 use crate::error::PackerError;
 
-use self::output::{save_output, JsonOutput, BinaryOutput, RonOutput, save_output_from, TemplateOutput, TomlOutput};
+use self::packer::{Algorithm, Placed, RectPacker};
+use self::cache::CacheManifest;
+use self::config_file::{ConfigLayer, ConfigReader};
+pub use self::unpack::{read_atlas, unpack, Unpacked};
+pub use self::check::{check, Diagnostic, Severity};
+use self::output::{save_output, JsonOutput, BinaryOutput, RonOutput, save_output_from, TemplateOutput, TomlOutput, GodotOutput};
 
 #[derive(serde::Deserialize, serde::Serialize, Clone)]
 #[serde(untagged)]
@@ -17,23 +31,143 @@ pub enum TemplatePath {
     Multiple(Vec<PathBuf>)
 }
 
+/// A folder of images, optionally filtered by glob patterns matched against
+/// the path relative to the folder or the name of the file.
+#[derive(serde::Serialize, Clone)]
+#[serde(untagged)]
+pub enum Folder {
+    Path(PathBuf),
+    Filtered {
+        path: PathBuf,
+        #[serde(default)]
+        include: Vec<String>,
+        #[serde(default)]
+        exclude: Vec<String>
+    }
+}
+
+impl Folder {
+    pub fn path(&self) -> &PathBuf {
+        match self {
+            Folder::Path(path) => path,
+            Folder::Filtered { path, .. } => path
+        }
+    }
+
+    fn filter(&self) -> anyhow::Result<PathFilter> {
+        match self {
+            Folder::Path(_) => PathFilter::new(&[], &[]),
+            Folder::Filtered { include, exclude, .. } => PathFilter::new(include, exclude)
+        }
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Folder {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(FolderVisitor)
+    }
+}
+
+/// Reads a path, or a table with the path and the patterns. Unlike an
+/// untagged enum, a misspelled key of the table is an error instead of
+/// being dropped.
+struct FolderVisitor;
+
+impl<'de> serde::de::Visitor<'de> for FolderVisitor {
+    type Value = Folder;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("a path, or a table with `path`, `include` and `exclude`")
+    }
+
+    fn visit_str<E: serde::de::Error>(self, value: &str) -> Result<Folder, E> {
+        Ok(Folder::Path(value.into()))
+    }
+
+    fn visit_map<A: serde::de::MapAccess<'de>>(self, mut map: A) -> Result<Folder, A::Error> {
+        const FIELDS: &[&str] = &["path", "include", "exclude"];
+        let (mut path, mut include, mut exclude) = (None, None, None);
+        while let Some(FolderKey(key)) = map.next_key()? {
+            match key.as_str() {
+                "path" => path = Some(map.next_value()?),
+                "include" => include = Some(map.next_value()?),
+                "exclude" => exclude = Some(map.next_value()?),
+                _ => return Err(serde::de::Error::unknown_field(&key, FIELDS))
+            }
+        }
+        Ok(Folder::Filtered {
+            path: path.ok_or_else(|| serde::de::Error::missing_field("path"))?,
+            include: include.unwrap_or_default(),
+            exclude: exclude.unwrap_or_default()
+        })
+    }
+}
+
+/// A key of a folder table. RON only gives the names of the fields as identifiers.
+struct FolderKey(String);
+
+impl<'de> serde::Deserialize<'de> for FolderKey {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct KeyVisitor;
+
+        impl serde::de::Visitor<'_> for KeyVisitor {
+            type Value = FolderKey;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a key of a folder")
+            }
+
+            fn visit_str<E: serde::de::Error>(self, value: &str) -> Result<FolderKey, E> {
+                Ok(FolderKey(value.to_owned()))
+            }
+        }
+
+        deserializer.deserialize_identifier(KeyVisitor)
+    }
+}
+
 const fn default_allow_normal_output() -> bool { true }
 
+/// The extensions of the images that are packed when `extensions` is not set.
+pub fn default_extensions() -> Vec<String> {
+    ["png", "jpg", "jpeg", "bmp", "tga", "gif", "webp", "qoi", "aseprite", "ase"]
+        .into_iter()
+        .map(String::from)
+        .collect()
+}
+
 #[derive(serde::Deserialize, serde::Serialize, Clone)]
 pub struct Config {
     pub name: String,
     pub output_path: PathBuf,
-    pub folders: Vec<PathBuf>,
+    pub folders: Vec<Folder>,
+    #[serde(default = "default_extensions")]
+    pub extensions: Vec<String>,
     #[serde(default = "default_allow_normal_output")]
     pub allow_normal_output: bool,
     pub template_path: Option<TemplatePath>,
+    #[serde(default)]
+    pub strict: bool,
 
     #[serde(default)]
     pub output_type: OutputType,
     #[serde(default)]
     pub image_options: ImageOptions,
     #[serde(default)]
-    pub features: Features
+    pub binary_options: BinaryOptions,
+    #[serde(default)]
+    pub features: Features,
+    /// Every config file this config extends, directly or through another one.
+    #[serde(skip)]
+    pub extended_from: Vec<PathBuf>
+}
+
+/// A path of the config, which is relative to the config file when there is one.
+fn relative_to_config(path: &Path, input_path: &Option<PathBuf>) -> PathBuf {
+    match input_path.as_ref().and_then(|x| x.parent()) {
+        Some(parent) => parent.join(path),
+        None => path.to_owned()
+    }
 }
 
 impl Config {
@@ -52,23 +186,131 @@ impl Config {
         PathBuf::from(path_string)
     }
 
+    /// Every path that affects the packed output: the config itself, the configs
+    /// it extends, the folders and the templates.
+    pub fn watched_paths(&self, input_path: &Option<PathBuf>) -> Vec<PathBuf> {
+        let mut paths = vec![];
+        if let Some(input_path) = input_path {
+            paths.push(input_path.to_owned());
+        }
+        paths.extend(self.extended_from.iter().cloned());
+        paths.extend(self.folder_paths(input_path));
+        paths.extend(self.template_paths(input_path));
+        paths
+    }
+
+    /// The folders, relative to the config.
+    pub fn folder_paths(&self, input_path: &Option<PathBuf>) -> Vec<PathBuf> {
+        self.folders.iter().map(|folder| relative_to_config(folder.path(), input_path)).collect()
+    }
+
+    /// The templates, relative to the config.
+    pub fn template_paths(&self, input_path: &Option<PathBuf>) -> Vec<PathBuf> {
+        let fixed_path = |path: &PathBuf| relative_to_config(path, input_path);
+        match &self.template_path {
+            Some(TemplatePath::Single(x)) => vec![fixed_path(x)],
+            Some(TemplatePath::Multiple(x)) => x.iter().map(fixed_path).collect(),
+            None => vec![]
+        }
+    }
+
+    /// The files written by packing the config, except the pages and the cache
+    /// manifest. The Godot output is the folder of the frame resources.
+    fn output_files(&self, input_path: &Option<PathBuf>) -> Vec<PathBuf> {
+        let mut file_path = self.fixed_output_path(input_path);
+        file_path.push(&self.name);
+
+        let mut files = vec![];
+        for template_path in self.template_paths(input_path) {
+            let extension = template_path.extension().unwrap_or_default();
+            files.push(file_path.with_extension(extension));
+        }
+        if self.allow_normal_output {
+            files.push(match self.output_type {
+                OutputType::Json => file_path.with_extension("json"),
+                OutputType::Binary => file_path.with_extension("bin"),
+                OutputType::Ron => file_path.with_extension("ron"),
+                OutputType::Toml => file_path.with_extension("toml"),
+                OutputType::Godot => file_path
+            });
+        }
+        files
+    }
+
+    /// Whether the file is one of the pages, the outputs, the Godot resources or
+    /// the cache manifest of the config. The file does not have to exist.
+    pub fn is_output(&self, input_path: &Option<PathBuf>, path: &Path) -> bool {
+        let canonical = |path: &Path| path.canonicalize().unwrap_or_else(|_| path.to_owned());
+        let path = match (path.parent(), path.file_name()) {
+            (Some(parent), Some(file_name)) => canonical(parent).join(file_name),
+            _ => path.to_owned()
+        };
+        let output_path = self.fixed_output_path(input_path);
+        let Ok(relative) = path.strip_prefix(canonical(&output_path)) else {
+            return false;
+        };
+        if relative == Path::new(&format!("{}.cache.json", self.name)) {
+            return true;
+        }
+
+        let outputs = self.output_files(input_path);
+        let outputs = outputs.iter().filter_map(|file| file.strip_prefix(&output_path).ok());
+        if outputs.into_iter().any(|output| relative == output) {
+            return true;
+        }
+        if self.allow_normal_output && matches!(self.output_type, OutputType::Godot) {
+            // The frame resources, and the SpriteFrames written when there are animations.
+            let sprite_frames = format!("{}.tres", self.name);
+            let is_resource = relative.extension().is_some_and(|extension| extension == "tres");
+            if is_resource && (relative.starts_with(&self.name) || relative == Path::new(&sprite_frames)) {
+                return true;
+            }
+        }
+
+        let extension = self.image_options.output_extension.extension();
+        if relative.parent() != Some(Path::new("")) || relative.extension().is_none_or(|ext| ext != extension) {
+            return false;
+        }
+        let stem = relative.file_stem().and_then(std::ffi::OsStr::to_str).unwrap_or_default();
+        stem == self.name || stem
+            .strip_prefix(&format!("{}_", self.name))
+            .is_some_and(|page| !page.is_empty() && page.bytes().all(|byte| byte.is_ascii_digit()))
+    }
 
-    pub fn from_json(path: &PathBuf) -> anyhow::Result<Config> {
-        let buffer = std::fs::read(path)?;
-        let packer_atlas = serde_json::from_slice::<Config>(&buffer)?;
-        Ok(packer_atlas)
+    /// Reads the config with the format matching the extension of the path.
+    /// Fails when the file describes several atlases, see `all_from_path`.
+    pub fn from_path(path: &Path) -> anyhow::Result<Config> {
+        Config::single(path, Config::all_from_path(path)?)
     }
 
-    pub fn from_ron(path: &PathBuf) -> anyhow::Result<Config> {
-        let buffer = std::fs::read_to_string(path)?;
-        let packer_atlas = ron::from_str::<Config>(&buffer)?;
-        Ok(packer_atlas)
+    /// Reads every atlas of a config file. The file either describes a single
+    /// atlas, or lists them in `atlas` while the settings at the top level are
+    /// shared by every atlas that does not set them.
+    ///
+    /// A config can take the settings it does not set from the config file in
+    /// `extends`. Its paths stay relative to the file that declared them, and
+    /// `${NAME}` in any path is replaced with the environment variable `NAME`.
+    pub fn all_from_path(path: &Path) -> anyhow::Result<Vec<Config>> {
+        ConfigLayer::from_path(path, &mut ConfigReader::default())?.into_configs()
     }
 
-    pub fn from_toml(path: &PathBuf) -> anyhow::Result<Config> {
-        let buffer = std::fs::read_to_string(path)?;
-        let packer_atlas = toml::from_str::<Config>(&buffer)?;
-        Ok(packer_atlas)
+    pub fn from_json(path: &Path) -> anyhow::Result<Config> {
+        Config::single(path, ConfigLayer::from_path_as(path, "json")?.into_configs()?)
+    }
+
+    pub fn from_ron(path: &Path) -> anyhow::Result<Config> {
+        Config::single(path, ConfigLayer::from_path_as(path, "ron")?.into_configs()?)
+    }
+
+    pub fn from_toml(path: &Path) -> anyhow::Result<Config> {
+        Config::single(path, ConfigLayer::from_path_as(path, "toml")?.into_configs()?)
+    }
+
+    fn single(path: &Path, mut configs: Vec<Config>) -> anyhow::Result<Config> {
+        if configs.len() != 1 {
+            Err(PackerError::InvalidConfig(format!("{} describes {} atlases", path.display(), configs.len())))?
+        }
+        Ok(configs.remove(0))
     }
 }
 
@@ -78,7 +320,43 @@ pub enum OutputType {
     Json,
     Binary,
     Ron,
-    Toml
+    Toml,
+    Godot
+}
+
+/// The layout of the `Binary` output.
+#[derive(serde::Deserialize, serde::Serialize, Default, Clone)]
+pub struct BinaryOptions {
+    #[serde(default)]
+    pub endianness: Endianness,
+    #[serde(default)]
+    pub coordinate_width: CoordinateWidth,
+    #[serde(default)]
+    pub string_encoding: StringEncoding
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Default, Clone, Copy)]
+pub enum Endianness {
+    #[default]
+    Little,
+    Big
+}
+
+/// The width of the coordinates and sizes. Signed values use the signed
+/// integer of the same width.
+#[derive(serde::Deserialize, serde::Serialize, Default, Clone, Copy)]
+pub enum CoordinateWidth {
+    #[default]
+    U32,
+    U16
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Default, Clone, Copy)]
+pub enum StringEncoding {
+    /// Prefixed with the length as a 7-bit encoded integer, like the `BinaryWriter` of .NET.
+    #[default]
+    LengthPrefixed,
+    NullTerminated
 }
 
 #[derive(serde::Deserialize, serde::Serialize, Default, clap::ValueEnum, Clone)]
@@ -89,22 +367,133 @@ pub enum OutputExtensionType {
     Jpg
 }
 
+impl OutputExtensionType {
+    fn extension(&self) -> &'static str {
+        match self {
+            OutputExtensionType::Png => "png",
+            OutputExtensionType::Qoi => "qoi",
+            OutputExtensionType::Jpg => "jpg"
+        }
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Default, Clone, Copy)]
+pub enum SizeMode {
+    #[default]
+    PowerOfTwo,
+    Any,
+    Square,
+    Fixed { width: usize, height: usize }
+}
+
+/// The algorithm placing the images on the sheet.
+#[derive(serde::Deserialize, serde::Serialize, Default, Clone, Copy)]
+pub enum PackingAlgorithm {
+    #[default]
+    Crunch,
+    MaxRects,
+    Skyline,
+    Guillotine,
+    Shelf,
+    /// Tries every algorithm and keeps the smallest sheets.
+    Best
+}
+
+/// How `MaxRects` picks the free area for an image.
+#[derive(serde::Deserialize, serde::Serialize, Default, Clone, Copy)]
+pub enum MaxRectsHeuristic {
+    #[default]
+    BestShortSideFit,
+    BestAreaFit,
+    BottomLeft
+}
+
+/// The order the images are handed to the packer, from the largest to the smallest.
+#[derive(serde::Deserialize, serde::Serialize, Default, Clone, Copy)]
+pub enum SortBy {
+    #[default]
+    Area,
+    Height,
+    Width,
+    Perimeter,
+    Name
+}
+
+/// The order of the frames in the written metadata. Ties are broken by name.
+#[derive(serde::Deserialize, serde::Serialize, Default, Clone, Copy)]
+pub enum FrameOrder {
+    #[default]
+    Name,
+    /// By page, then from top to bottom and left to right.
+    Position,
+    /// From the largest to the smallest area.
+    Area
+}
+
 #[derive(serde::Deserialize, serde::Serialize, Clone)]
 pub struct ImageOptions {
     #[serde(default)]
     pub output_extension: OutputExtensionType,
-    max_size: usize,
-    show_extension: bool,
+    pub max_size: usize,
+    pub show_extension: bool,
+    #[serde(default)]
+    pub size_mode: SizeMode,
+    #[serde(default)]
+    pub trim: bool,
+    #[serde(default)]
+    pub allow_rotation: bool,
+    #[serde(default)]
+    pub padding: u32,
+    #[serde(default)]
+    pub border: u32,
+    #[serde(default)]
+    pub extrude: u32,
+    #[serde(default)]
+    pub remove_duplicates: bool,
+    #[serde(default)]
+    pub show_aliases: bool,
+    #[serde(default)]
+    pub frame_order: FrameOrder,
+    #[serde(default)]
+    pub packer: PackingAlgorithm,
+    #[serde(default)]
+    pub heuristic: MaxRectsHeuristic,
+    #[serde(default)]
+    pub sort_by: SortBy,
 }
 
-#[derive(serde::Deserialize, serde::Serialize, Clone, Default)]
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
 pub struct Features {
     #[serde(default)]
-    nine_patch: bool,
+    pub nine_patch: bool,
     #[serde(default)]
-    aseprite: bool,
+    pub aseprite: bool,
     #[serde(default)]
-    ase_sheet: bool
+    pub ase_sheet: bool,
+    #[serde(default)]
+    pub ase_split_layers: bool,
+    #[serde(default)]
+    pub ase_include_layers: Vec<String>,
+    #[serde(default)]
+    pub ase_exclude_layers: Vec<String>,
+    #[serde(default = "default_ase_ignore_hidden")]
+    pub ase_ignore_hidden: bool
+}
+
+const fn default_ase_ignore_hidden() -> bool { true }
+
+impl Default for Features {
+    fn default() -> Self {
+        Features {
+            nine_patch: false,
+            aseprite: false,
+            ase_sheet: false,
+            ase_split_layers: false,
+            ase_include_layers: vec![],
+            ase_exclude_layers: vec![],
+            ase_ignore_hidden: true,
+        }
+    }
 }
 
 impl Default for ImageOptions {
@@ -113,76 +502,367 @@ impl Default for ImageOptions {
             output_extension: OutputExtensionType::default(),
             max_size: 1024,
             show_extension: true,
+            size_mode: SizeMode::default(),
+            trim: false,
+            allow_rotation: false,
+            padding: 0,
+            border: 0,
+            extrude: 0,
+            remove_duplicates: false,
+            show_aliases: false,
+            frame_order: FrameOrder::default(),
+            packer: PackingAlgorithm::default(),
+            heuristic: MaxRectsHeuristic::default(),
+            sort_by: SortBy::default(),
         }
     }
 }
 
-#[derive(Default, serde::Serialize, Clone)]
-struct PackerAtlas {
-    sheet_path: PathBuf,
-    frames: HashMap<String, TextureData>
+impl ImageOptions {
+    /// The size of a sheet that is filled up before spilling over into the next page.
+    fn page_size(&self) -> (usize, usize) {
+        match self.size_mode {
+            SizeMode::Fixed { width, height } => (width, height),
+            _ => (self.max_size, self.max_size)
+        }
+    }
+
+    /// The area of a `width` x `height` sheet where the items can be placed.
+    ///
+    /// Every item is followed by the padding on its right and bottom side,
+    /// so the area is made larger by the padding to let the last item touch the border.
+    fn packing_area(&self, width: usize, height: usize) -> Option<crunch::Rect> {
+        let border = self.border as usize * 2;
+        if width <= border || height <= border {
+            return None;
+        }
+        let padding = self.padding as usize;
+        Some(crunch::Rect::new(
+            self.border as usize, self.border as usize,
+            width - border + padding, height - border + padding
+        ))
+    }
+}
+
+#[derive(Default, serde::Deserialize, serde::Serialize, Clone, PartialEq, Debug)]
+pub struct PackerAtlas {
+    pub sheet_path: PathBuf,
+    #[serde(default)]
+    pub pages: Vec<PathBuf>,
+    pub frames: IndexMap<String, TextureData>,
+    #[serde(default)]
+    pub animations: Vec<Animation>
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, PartialEq, Debug)]
+pub struct Animation {
+    pub name: String,
+    pub direction: AnimationDirection,
+    pub frames: Vec<AnimationFrame>
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, PartialEq, Debug)]
+pub struct AnimationFrame {
+    pub name: String,
+    /// The duration of the frame in milliseconds.
+    pub duration: u32
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq, Debug)]
+pub enum AnimationDirection {
+    Forward,
+    Reverse,
+    PingPong
+}
+
+impl From<asefile::AnimationDirection> for AnimationDirection {
+    fn from(direction: asefile::AnimationDirection) -> Self {
+        match direction {
+            asefile::AnimationDirection::Forward => AnimationDirection::Forward,
+            asefile::AnimationDirection::Reverse => AnimationDirection::Reverse,
+            asefile::AnimationDirection::PingPong => AnimationDirection::PingPong,
+        }
+    }
 }
 
 impl PackerAtlas {
-    fn add(
-        &mut self,
-        name: &str,
-        x: u32, y: u32,
-        width: u32, height: u32,
-        nine_patch: Option<Rect>
-    ) {
-        self.frames.insert(name.into(), TextureData {
-            x, y, width, height, nine_patch
-        });
+    fn add(&mut self, name: &str, data: TextureData) {
+        self.frames.insert(name.into(), data);
     }
 
-    fn add_sheet_path(&mut self, path: &Path) {
-        self.sheet_path = path.to_path_buf();
+    fn sort_frames(&mut self, order: FrameOrder) {
+        match order {
+            FrameOrder::Name => self.frames.sort_keys(),
+            FrameOrder::Position => self.frames.sort_by(|k1, v1, k2, v2| {
+                (v1.page, v1.y, v1.x, k1).cmp(&(v2.page, v2.y, v2.x, k2))
+            }),
+            FrameOrder::Area => self.frames.sort_by(|k1, v1, k2, v2| {
+                let area = |data: &TextureData| data.width as u64 * data.height as u64;
+                area(v2).cmp(&area(v1)).then_with(|| k1.cmp(k2))
+            })
+        }
+    }
+
+    fn add_page(&mut self, path: &Path) {
+        if self.pages.is_empty() {
+            self.sheet_path = path.to_path_buf();
+        }
+        self.pages.push(path.to_path_buf());
     }
 }
 
-#[derive(serde::Deserialize, serde::Serialize, Clone, Copy)]
-struct Rect {
-    x: u32, y: u32,
-    w: u32, h: u32,
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq, Debug)]
+pub struct Rect {
+    pub x: u32, pub y: u32,
+    pub w: u32, pub h: u32,
 }
 
-#[derive(serde::Serialize, Clone)]
-struct TextureData {
-    x: u32,
-    y: u32,
-    width: u32,
-    height: u32,
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq, Debug)]
+pub struct Point {
+    pub x: i32, pub y: i32,
+}
+
+/// A named region of an Aseprite frame, relative to the original image.
+#[derive(serde::Deserialize, serde::Serialize, Clone, PartialEq, Debug)]
+pub struct Slice {
+    pub name: String,
+    pub x: i32, pub y: i32,
+    pub w: u32, pub h: u32,
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(default)]
-    nine_patch: Option<Rect>
+    pub nine_patch: Option<Rect>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub pivot: Option<Point>
 }
 
-struct ImageTexture {
+#[derive(serde::Deserialize, serde::Serialize, Clone, PartialEq, Debug)]
+pub struct TextureData {
+    pub page: usize,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub source_width: u32,
+    pub source_height: u32,
+    pub offset_x: u32,
+    pub offset_y: u32,
+    pub rotated: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub nine_patch: Option<Rect>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub pivot: Option<Point>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[serde(default)]
+    pub slices: Vec<Slice>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub alias_of: Option<String>
+}
+
+pub struct ImageTexture {
     name: String,
     img: RgbaImage,
-    nine_patch: Option<Rect>
+    nine_patch: Option<Rect>,
+    source_width: u32,
+    source_height: u32,
+    offset_x: u32,
+    offset_y: u32,
+    pivot: Option<Point>,
+    slices: Vec<Slice>,
+    duplicates: Vec<ImageTexture>
 }
 
 impl ImageTexture {
-    const fn new(name: String, img: RgbaImage, nine_patch: Option<Rect>) -> Self {
+    pub fn new(name: String, img: RgbaImage, nine_patch: Option<Rect>) -> Self {
+        let (source_width, source_height) = img.dimensions();
         ImageTexture {
-            name, img, nine_patch
+            name, img, nine_patch,
+            source_width, source_height,
+            offset_x: 0, offset_y: 0,
+            pivot: None,
+            slices: vec![],
+            duplicates: vec![]
         }
     }
-}
 
-fn visit_dir(dir: PathBuf, collector: &mut Vec<PathBuf>) -> anyhow::Result<()> {
-    if dir.is_dir() {
-        let paths = std::fs::read_dir(dir)?;
-        for path in paths {
-            let path = path?.path();
-            if path.is_dir() {
-                visit_dir(path, collector)?;
-            } else {
-                collector.push(path);
+    /// Takes the nine patch and the pivot from the first slices that have them,
+    /// unless the image already has a nine patch.
+    fn with_slices(mut self, slices: Vec<Slice>) -> Self {
+        if self.nine_patch.is_none() {
+            self.nine_patch = slices.iter().find_map(|slice| slice.nine_patch);
+        }
+        self.pivot = slices.iter().find_map(|slice| slice.pivot);
+        self.slices = slices;
+        self
+    }
+
+    /// Crops the fully transparent borders of the image, remembering where
+    /// the remaining pixels were placed in the original image, and moves the
+    /// nine patch and the pivot along with them.
+    fn trim(&mut self) {
+        let (width, height) = self.img.dimensions();
+        let (mut left, mut top, mut right, mut bottom) = (width, height, 0, 0);
+        for (x, y, pixel) in self.img.enumerate_pixels() {
+            if pixel[3] == 0 {
+                continue;
             }
+            left = left.min(x);
+            top = top.min(y);
+            right = right.max(x + 1);
+            bottom = bottom.max(y + 1);
+        }
+
+        // Keep a single pixel of a fully transparent image, so it still has
+        // a place in the atlas.
+        if left >= right || top >= bottom {
+            (left, top, right, bottom) = (0, 0, width.min(1), height.min(1));
+        }
+
+        if (left, top, right, bottom) == (0, 0, width, height) {
+            return;
+        }
+
+        let (width, height) = (right - left, bottom - top);
+        self.img = self.img.view(left, top, width, height).to_image();
+        self.offset_x += left;
+        self.offset_y += top;
+
+        // The part of the nine patch that was cropped away is dropped.
+        self.nine_patch = self.nine_patch.map(|patch| {
+            let x = patch.x.saturating_sub(left).min(width);
+            let y = patch.y.saturating_sub(top).min(height);
+            let right = (patch.x + patch.w).saturating_sub(left).min(width);
+            let bottom = (patch.y + patch.h).saturating_sub(top).min(height);
+            Rect { x, y, w: right - x, h: bottom - y }
+        });
+        self.pivot = self.pivot.map(|pivot| Point {
+            x: pivot.x - left as i32,
+            y: pivot.y - top as i32
+        });
+    }
+}
+
+/// Moves every image that has the same pixels as an earlier image into the
+/// `duplicates` of the earlier one, so it is only packed once.
+fn remove_duplicates(images: Vec<ImageTexture>) -> Vec<ImageTexture> {
+    let image_hashes: Vec<u64> = images
+        .par_iter()
+        .map(|image| {
+            let mut hasher = std::collections::hash_map::DefaultHasher::new();
+            image.img.hash(&mut hasher);
+            hasher.finish()
+        })
+        .collect();
+
+    let mut unique: Vec<ImageTexture> = vec![];
+    let mut hashes: HashMap<u64, Vec<usize>> = HashMap::new();
+    for (image, hash) in images.into_iter().zip(image_hashes) {
+        let candidates = hashes.entry(hash).or_default();
+
+        if let Some(&i) = candidates.iter().find(|&&i| unique[i].img == image.img) {
+            unique[i].duplicates.push(image);
+        } else {
+            candidates.push(unique.len());
+            unique.push(image);
+        }
+    }
+    unique
+}
+
+/// The name of the file listing the patterns to skip inside a source directory.
+const IGNORE_FILE: &str = ".clutteredignore";
+
+fn glob_set(patterns: &[String]) -> anyhow::Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(Glob::new(pattern)?);
+    }
+    Ok(builder.build()?)
+}
+
+/// Matches a path relative to a directory, or only its name, against the patterns.
+fn matches_path(set: &GlobSet, relative: &Path) -> bool {
+    set.is_match(relative) || relative.file_name().is_some_and(|name| set.is_match(name))
+}
+
+struct PathFilter {
+    include: Option<GlobSet>,
+    exclude: GlobSet
+}
+
+impl PathFilter {
+    fn new(include: &[String], exclude: &[String]) -> anyhow::Result<Self> {
+        Ok(PathFilter {
+            include: if include.is_empty() { None } else { Some(glob_set(include)?) },
+            exclude: glob_set(exclude)?
+        })
+    }
+}
+
+/// Reads the patterns of the ignore file inside the directory, skipping
+/// empty lines and comments.
+fn read_ignore_file(dir: &Path) -> anyhow::Result<Option<GlobSet>> {
+    let Ok(file) = std::fs::read_to_string(dir.join(IGNORE_FILE)) else {
+        return Ok(None);
+    };
+    let patterns: Vec<String> = file.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.trim_end_matches('/').to_owned())
+        .collect();
+    Ok(Some(glob_set(&patterns)?))
+}
+
+fn visit_folder(
+    root: PathBuf,
+    filter: &PathFilter,
+    collector: &mut Vec<PathBuf>
+) -> anyhow::Result<()> {
+    if !root.is_dir() {
+        return Err(PackerError::MissingFolder(root).into());
+    }
+    visit_dir(&root, &root, filter, &mut vec![], collector)
+}
+
+fn visit_dir(
+    root: &Path,
+    dir: &Path,
+    filter: &PathFilter,
+    ignores: &mut Vec<(PathBuf, GlobSet)>,
+    collector: &mut Vec<PathBuf>
+) -> anyhow::Result<()> {
+    let ignore_file = read_ignore_file(dir)?;
+    let has_ignore_file = ignore_file.is_some();
+    if let Some(set) = ignore_file {
+        ignores.push((dir.to_owned(), set));
+    }
+
+    // Sorted, since the order of `read_dir` depends on the platform and file system.
+    let mut paths = std::fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    paths.sort();
+
+    for path in paths {
+        let relative = path.strip_prefix(root).unwrap_or(&path);
+        let ignored = ignores.iter().any(|(ignore_dir, set)| {
+            matches_path(set, path.strip_prefix(ignore_dir).unwrap_or(&path))
+        });
+        if ignored || matches_path(&filter.exclude, relative) {
+            continue;
         }
+        if path.is_dir() {
+            visit_dir(root, &path, filter, ignores, collector)?;
+        } else if filter.include.as_ref().is_none_or(|set| matches_path(set, relative)) {
+            collector.push(path);
+        }
+    }
+
+    if has_ignore_file {
+        ignores.pop();
     }
     Ok(())
 }
@@ -206,114 +886,351 @@ fn find_nine_patch_file(filename: &Path) -> Option<Rect> {
 
 type Texture2D = ImageBuffer<Rgba<u8>, Vec<u8>>;
 
-fn save_as(mut path: PathBuf, texture: Texture2D, output_ext: &OutputExtensionType)
+fn save_as(path: PathBuf, texture: Texture2D, output_ext: &OutputExtensionType)
     -> anyhow::Result<PathBuf> {
-    let ext: String = match output_ext {
+    let path = path.with_extension(output_ext.extension());
+    match output_ext {
         OutputExtensionType::Png => {
-            let path = path.with_extension("png");
-            texture.save_with_format(path, image::ImageFormat::Png)?;
-            "png".into()
+            texture.save_with_format(&path, image::ImageFormat::Png)?;
         }
         OutputExtensionType::Qoi => {
-            let path = path.with_extension("qoi");
             let bytes = texture.to_vec();
             let encoded = rapid_qoi::Qoi {
                 width: texture.width(),
                 height: texture.height(),
                 colors: rapid_qoi::Colors::Rgba
             };
-            let file = std::fs::File::create(path)?;
+            let file = std::fs::File::create(&path)?;
             let out_bytes = encoded.encode_alloc(&bytes)?;
             let mut buf = std::io::BufWriter::new(file);
             let _ = buf.write(&out_bytes)?;
-            "qoi".into()
         },
         OutputExtensionType::Jpg => {
-            let path = path.with_extension("jpg");
-            texture.save_with_format(path, image::ImageFormat::Jpeg)?;
-            "jpg".into()
+            texture.save_with_format(&path, image::ImageFormat::Jpeg)?;
         }
     };
-    path.push(ext);
     Ok(path)
 }
 
-pub fn pack(config: Config, input_path: Option<PathBuf>) -> anyhow::Result<()> {
-    let mut image_paths = vec![];
+/// The packed sheets together with the metadata of every frame.
+pub struct PackedAtlas {
+    pub sheets: Vec<RgbaImage>,
+    pub atlas: PackerAtlas,
+    pub report: PackReport
+}
+
+/// What happened while packing, for the caller to show.
+#[derive(Default, Clone)]
+pub struct PackReport {
+    /// The files that could not be read, with the reason.
+    pub skipped: Vec<(PathBuf, String)>,
+    /// The files that were read, but may not look the same as in their editor.
+    pub warnings: Vec<(PathBuf, String)>,
+    /// The packer the sheets were packed with.
+    pub packer: PackerScore,
+    /// In `Best` mode, every packer that was tried, including the picked one.
+    pub candidates: Vec<PackerScore>,
+    /// In `Best` mode, the packers that could not fit the images, with the reason.
+    pub failed_packers: Vec<(String, String)>
+}
+
+/// How well a packer did with the images.
+#[derive(Default, Clone)]
+pub struct PackerScore {
+    pub name: String,
+    pub pages: usize,
+    /// The area of all the sheets, in pixels.
+    pub area: usize,
+    /// The percentage of the area covered by images.
+    pub occupancy: f64
+}
+
+/// Packs the images of the config and writes the sheets and the outputs.
+pub fn pack(config: Config, input_path: Option<PathBuf>) -> anyhow::Result<PackReport> {
+    check_output(&config)?;
+    let mut packed = pack_config(&config, &input_path)?;
+    let report = std::mem::take(&mut packed.report);
+    save(&config, &input_path, packed)?;
+    Ok(report)
+}
+
+/// What `pack_cached` did with a config.
+pub enum PackStatus {
+    /// Nothing changed since the last pack.
+    UpToDate,
+    Packed { frames: usize, pages: usize, report: PackReport }
+}
+
+/// Packs like `pack`, unless the inputs, the config and the templates did not
+/// change since the last time and the outputs are still there. `force` packs anyway.
+pub fn pack_cached(config: Config, input_path: Option<PathBuf>, force: bool) -> anyhow::Result<PackStatus> {
+    check_output(&config)?;
+    let last = CacheManifest::load(&config, &input_path);
+    let manifest = CacheManifest::new(&config, &input_path, last.as_ref())?;
+    if !force && last.is_some_and(|last| last.is_up_to_date(&manifest)) {
+        return Ok(PackStatus::UpToDate);
+    }
+
+    let mut packed = pack_config(&config, &input_path)?;
+    let frames = packed.atlas.frames.len();
+    let pages = packed.sheets.len();
+    let report = std::mem::take(&mut packed.report);
+    save(&config, &input_path, packed)?;
+    manifest.with_outputs(&config, &input_path, pages).save(&config, &input_path)?;
+    Ok(PackStatus::Packed { frames, pages, report })
+}
+
+/// Refuses the options the outputs of the config cannot represent, before
+/// anything is packed.
+fn check_output(config: &Config) -> Result<(), PackerError> {
+    let godot = config.allow_normal_output && matches!(config.output_type, OutputType::Godot);
+    if godot && config.image_options.allow_rotation {
+        return Err(PackerError::RotatedGodotFrames);
+    }
+    Ok(())
+}
+
+/// Packs every config with `pack_cached`, returning what happened to each of
+/// them by name. A failing config does not stop the others.
+pub fn pack_all(
+    configs: Vec<Config>,
+    input_path: Option<PathBuf>,
+    force: bool
+) -> Vec<(String, anyhow::Result<PackStatus>)> {
+    configs
+        .into_iter()
+        .map(|config| (config.name.clone(), pack_cached(config, input_path.clone(), force)))
+        .collect()
+}
+
+/// Packs the images of the config without writing anything.
+pub fn pack_config(config: &Config, input_path: &Option<PathBuf>) -> anyhow::Result<PackedAtlas> {
+    let LoadedImages { images, animations, skipped, warnings } = load_images(config, input_path)?;
+    let mut packed = pack_images(images, animations, &config.image_options)?;
+    packed.report.skipped = skipped;
+    packed.report.warnings = warnings;
+    Ok(packed)
+}
+
+/// Every file inside the folders of the config that is not filtered out, nor
+/// written by a previous pack of the config.
+fn source_files(config: &Config, input_path: &Option<PathBuf>) -> anyhow::Result<Vec<PathBuf>> {
+    let mut paths = vec![];
 
     for folder in config.folders.iter() {
+        let filter = folder.filter()?;
         if let Some(ref path) = input_path {
             if let Some(parent) = path.parent() {
-                let parent = parent.join(folder);
-                visit_dir(parent, &mut image_paths)?;
+                let parent = parent.join(folder.path());
+                visit_folder(parent, &filter, &mut paths)?;
                 continue;
             }
         }
 
-        visit_dir(folder.to_path_buf(), &mut image_paths)?;
+        visit_folder(folder.path().to_owned(), &filter, &mut paths)?;
     }
-    let mut temp_ase: Vec<ImageTexture> = vec![];
+    paths.retain(|path| !config.is_output(input_path, path));
+    Ok(paths)
+}
 
-    let mut images = image_paths.iter().filter_map(|file| {
-        let mut ext = "png";
-        if get_extension_from_filename(file) != Some("png") {
-            if config.features.aseprite {
-                if get_extension_from_filename(file) != Some("aseprite") {
-                    return None;
-                }
-                ext = "aseprite";
-            } else {
-                return None;
+/// The images read from the folders of a config.
+pub struct LoadedImages {
+    pub images: Vec<ImageTexture>,
+    /// The animations of the Aseprite files and the animated GIFs.
+    pub animations: Vec<Animation>,
+    /// The files that could not be read, with the reason.
+    pub skipped: Vec<(PathBuf, String)>,
+    /// The files that were read, but may not look the same as in their editor.
+    pub warnings: Vec<(PathBuf, String)>
+}
+
+/// Reads every image inside the folders of the config, together with the
+/// animations of the Aseprite files.
+pub fn load_images(config: &Config, input_path: &Option<PathBuf>) -> anyhow::Result<LoadedImages> {
+    let image_paths = source_files(config, input_path)?;
+    let loaded: Vec<Option<Loaded>> = image_paths
+        .par_iter()
+        .map(|file| load_file(config, input_path, file))
+        .collect();
+
+    // Collected in the order of the paths, so the result does not depend on
+    // which file finished decoding first.
+    let mut images: Vec<ImageTexture> = vec![];
+    let mut temp_frames: Vec<ImageTexture> = vec![];
+    let mut animations: Vec<Animation> = vec![];
+    let mut skipped: Vec<(PathBuf, String)> = vec![];
+    let mut warnings: Vec<(PathBuf, String)> = vec![];
+    for (file, loaded) in image_paths.into_iter().zip(loaded) {
+        match loaded {
+            Some(Loaded::Image(image)) => images.push(image),
+            Some(Loaded::Frames(mut frames, mut frame_animations, warning)) => {
+                temp_frames.append(&mut frames);
+                animations.append(&mut frame_animations);
+                warnings.extend(warning.map(|warning| (file, warning)));
             }
+            Some(Loaded::Failed(reason)) => skipped.push((file, reason)),
+            None => {}
         }
+    }
 
-        println!("Found Image: {}", file.display());
-        let nine_patch = if config.features.nine_patch {
-            find_nine_patch_file(file)
-        } else { None };
-        let filename = if !config.image_options.show_extension {
-            file.with_extension("").to_str().unwrap_or_default().to_owned()
-        } else {
-            file.to_str().unwrap_or_default().to_owned()
-        };
+    images.append(&mut temp_frames);
 
-        let filename = filename
-            .replace('\\', "/")
-            .replace("./", "");
+    if config.strict && !skipped.is_empty() {
+        return Err(PackerError::UnreadableImages(skipped).into());
+    }
 
-        let filename = if let Some(ref path) = input_path {
-            if let Some(parent) = path.parent() {
-                let parent: String = parent.to_str().unwrap_or_default().into();
-                filename.trim_start_matches(&format!("{parent}/")).into()
-            } else {
-                filename
-            }
+    Ok(LoadedImages { images, animations, skipped, warnings })
+}
+
+/// The lowercase extension of the file, if it is an image the config packs.
+fn packed_extension(config: &Config, file: &Path) -> Option<String> {
+    let ext = get_extension_from_filename(file)?.to_lowercase();
+    if !config.extensions.iter().any(|allowed| allowed.eq_ignore_ascii_case(&ext)) {
+        return None;
+    }
+    let is_ase = ext == "aseprite" || ext == "ase";
+    if is_ase && !config.features.aseprite {
+        return None;
+    }
+    Some(ext)
+}
+
+/// What was read from a single source file.
+enum Loaded {
+    Image(ImageTexture),
+    /// The frames of an Aseprite file or an animated GIF, with a warning
+    /// when they may not look the same as in their editor.
+    Frames(Vec<ImageTexture>, Vec<Animation>, Option<String>),
+    Failed(String)
+}
+
+/// Reads a single source file, or returns `None` when it is not an image
+/// that should be packed.
+fn load_file(config: &Config, input_path: &Option<PathBuf>, file: &Path) -> Option<Loaded> {
+    let ext = packed_extension(config, file)?;
+    let is_ase = ext == "aseprite" || ext == "ase";
+
+    let nine_patch = if config.features.nine_patch {
+        find_nine_patch_file(file)
+    } else { None };
+    let filename = if !config.image_options.show_extension {
+        file.with_extension("").to_str().unwrap_or_default().to_owned()
+    } else {
+        file.to_str().unwrap_or_default().to_owned()
+    };
+
+    let filename = filename
+        .replace('\\', "/")
+        .split('/')
+        .filter(|component| *component != ".")
+        .collect::<Vec<_>>()
+        .join("/");
+
+    let filename = if let Some(ref path) = input_path {
+        if let Some(parent) = path.parent() {
+            let parent: String = parent.to_str().unwrap_or_default().into();
+            filename.trim_start_matches(&format!("{parent}/")).into()
         } else {
             filename
+        }
+    } else {
+        filename
+    };
+
+    let loaded = if is_ase {
+        AsepriteFile::read_file(file)
+            .map_err(anyhow::Error::from)
+            .and_then(|ase| {
+                let warning = ase_blend_warning(&ase, &config.features)?;
+                let (images, animations) = process_ase(ase, filename, nine_patch, &config.features)?;
+                Ok(Loaded::Frames(images, animations, warning))
+            })
+    } else if ext == "gif" {
+        read_gif(file, filename, nine_patch)
+            .map(|(images, animations)| Loaded::Frames(images, animations, None))
+    } else {
+        read_image(file, &ext).map(|img| Loaded::Image(ImageTexture::new(filename, img, nine_patch)))
+    };
+    Some(loaded.unwrap_or_else(|err| Loaded::Failed(err.to_string())))
+}
+
+/// Decodes a single image, using `rapid_qoi` for QOI files and the
+/// `image` crate for everything else.
+fn read_image(file: &Path, ext: &str) -> anyhow::Result<RgbaImage> {
+    if ext != "qoi" {
+        return Ok(image::open(file)?.to_rgba8());
+    }
+    let bytes = std::fs::read(file)?;
+    let (qoi, pixels) = rapid_qoi::Qoi::decode_alloc(&bytes)?;
+    let img = if qoi.colors.has_alpha() {
+        RgbaImage::from_raw(qoi.width, qoi.height, pixels)
+    } else {
+        RgbImage::from_raw(qoi.width, qoi.height, pixels)
+            .map(|img| DynamicImage::ImageRgb8(img).to_rgba8())
+    };
+    img.ok_or_else(|| anyhow::anyhow!("Decoded QOI data does not match its size"))
+}
+
+/// Decodes a GIF. Animated GIFs are expanded into numbered frames with an
+/// animation named after the file, like multi-frame Aseprite files.
+fn read_gif(
+    file: &Path,
+    filename: String,
+    nine_patch: Option<Rect>
+) -> anyhow::Result<(Vec<ImageTexture>, Vec<Animation>)> {
+    let decoder = GifDecoder::new(BufReader::new(std::fs::File::open(file)?))?;
+    let mut frames = decoder.into_frames().collect_frames()?;
+    if frames.len() <= 1 {
+        let Some(frame) = frames.pop() else {
+            anyhow::bail!("GIF has no frames");
         };
-        if ext == "aseprite" {
-            let Ok(ase) = AsepriteFile::read_file(file) else { return None };
-            let Ok(mut images) = process_ase(ase, filename, nine_patch, config.features.ase_sheet) else { return None };
+        return Ok((vec![ImageTexture::new(filename, frame.into_buffer(), nine_patch)], vec![]));
+    }
 
-            temp_ase.append(&mut images);
+    let mut animation = Animation {
+        name: filename.clone(),
+        direction: AnimationDirection::Forward,
+        frames: vec![]
+    };
+    let images = frames.into_iter().enumerate().map(|(i, frame)| {
+        let name = format!("{}/{}", filename, i);
+        let (numer, denom) = frame.delay().numer_denom_ms();
+        animation.frames.push(AnimationFrame {
+            name: name.clone(),
+            duration: numer / denom.max(1)
+        });
+        ImageTexture::new(name, frame.into_buffer(), nine_patch)
+    }).collect();
 
-            None
-        } else {
-            let Ok(img) = image::open(file) else { return None };
+    Ok((images, vec![animation]))
+}
 
-            println!("{}", filename);
-            Some(ImageTexture::new(filename, img.to_rgba8(), nine_patch))
-        }
-    }).collect::<Vec<ImageTexture>>();
+/// Packs the images into as many sheets as needed.
+pub fn pack_images(
+    mut images: Vec<ImageTexture>,
+    animations: Vec<Animation>,
+    options: &ImageOptions
+) -> anyhow::Result<PackedAtlas> {
+    let mut atlas_json = PackerAtlas {
+        animations,
+        ..Default::default()
+    };
 
-    images.append(&mut temp_ase);
+    if options.trim {
+        images.par_iter_mut().for_each(ImageTexture::trim);
+    }
+
+    if options.remove_duplicates {
+        images = remove_duplicates(images);
+    }
+
+    let (pages, report) = pack_pages(&images, options)?;
 
-    let items = images.iter().map(|img|
-        Item::new(img, img.img.width() as usize, img.img.height() as usize, Rotation::None)
-    ).collect::<Vec<Item<&ImageTexture>>>();
+    let mut sheets = vec![];
+    let extrude = options.extrude;
+    let spacing = extrude * 2 + options.padding;
 
-    if let Ok((w, h, packed)) = crunch::pack_into_po2(config.image_options.max_size, items) {
-        let mut atlas_json = PackerAtlas::default();
+    for (index, (w, h, packed)) in pages.into_iter().enumerate() {
         let mut atlas: RgbaImage = ImageBuffer::from_fn(
             w as u32,
             h as u32,
@@ -322,68 +1239,478 @@ pub fn pack(config: Config, input_path: Option<PathBuf>) -> anyhow::Result<()> {
 
         // Pack all images
         for (rect, image_data) in packed {
-            let (x, y) = (rect.x as u32, rect.y as u32);
-            let (width, height) = (rect.w as u32, rect.h as u32);
+            let (width, height) = (rect.w as u32 - spacing, rect.h as u32 - spacing);
 
-            let view = image_data.img.view(0, 0, width, height).to_image();
-            atlas.copy_from(&view, x, y)?;
-            atlas_json.add(
-                &image_data.name, x, y, rect.w as u32, rect.h as u32, image_data.nine_patch);
-        }
+            // crunch swaps the width and height of the items it rotated.
+            let rotated = width != image_data.img.width();
+            let view = if rotated {
+                extrude_edges(&image::imageops::rotate90(&image_data.img), extrude)
+            } else {
+                extrude_edges(&image_data.img, extrude)
+            };
+            atlas.copy_from(&view, rect.x as u32, rect.y as u32)?;
 
-        let config_path = config.fixed_output_path(&input_path);
+            let (x, y) = (rect.x as u32 + extrude, rect.y as u32 + extrude);
+            let texture_data = |texture: &ImageTexture, alias_of| TextureData {
+                page: index,
+                x, y, width, height,
+                source_width: texture.source_width,
+                source_height: texture.source_height,
+                offset_x: texture.offset_x,
+                offset_y: texture.offset_y,
+                rotated,
+                nine_patch: texture.nine_patch,
+                pivot: texture.pivot,
+                slices: texture.slices.clone(),
+                alias_of
+            };
+            atlas_json.add(&image_data.name, texture_data(image_data, None));
 
-        if !config_path.is_dir() {
-            std::fs::create_dir_all(&config_path)?;
+            // Duplicates share the same area of the sheet.
+            for duplicate in image_data.duplicates.iter() {
+                let alias_of = if options.show_aliases {
+                    Some(image_data.name.clone())
+                } else { None };
+                atlas_json.add(&duplicate.name, texture_data(duplicate, alias_of));
+            }
         }
 
-        let mut path = config.fixed_output_path(&input_path);
-        path.push(&config.name);
+        sheets.push(atlas);
+    }
+    atlas_json.sort_frames(options.frame_order);
+
+    Ok(PackedAtlas {
+        sheets,
+        atlas: atlas_json,
+        report
+    })
+}
+
+/// Writes the packed sheets and the outputs of the config.
+pub fn save(config: &Config, input_path: &Option<PathBuf>, packed: PackedAtlas) -> anyhow::Result<()> {
+    let config_path = config.fixed_output_path(input_path);
+
+    if !config_path.is_dir() {
+        std::fs::create_dir_all(&config_path)?;
+    }
+
+    let PackedAtlas { sheets, atlas: mut atlas_json, .. } = packed;
+    let page_count = sheets.len();
+
+    for (index, atlas) in sheets.into_iter().enumerate() {
+        let path = page_path(config, input_path, index, page_count);
         let ext = save_as(path, atlas, &config.image_options.output_extension)?;
-        atlas_json.add_sheet_path(&ext);
+        atlas_json.add_page(&ext);
+    }
 
+    let mut file_path = config.fixed_output_path(input_path);
+    file_path.push(&config.name);
 
-        let mut file_path = config.fixed_output_path(&input_path);
-        file_path.push(&config.name);
 
+    let template_path = config.template_path.to_owned();
+    if let Some(template_path) = template_path {
+        // The template data only keeps the order of a map when serde_json is
+        // built with `preserve_order`, so the frames are always sorted by name.
+        let mut atlas = atlas_json.clone();
+        atlas.sort_frames(FrameOrder::Name);
+        save_output_from(TemplateOutput(config, template_path, input_path), file_path.clone(), atlas)?
+    }
 
-        let template_path = config.template_path.to_owned();
-        if let Some(template_path) = template_path {
-            save_output_from(
-                TemplateOutput(&config, template_path, &input_path), file_path.clone(), atlas_json.clone())?
+    if config.allow_normal_output {
+        match config.output_type {
+            OutputType::Json => save_output::<JsonOutput>(file_path, atlas_json)?,
+            OutputType::Binary => save_output_from(
+                BinaryOutput(config), file_path, atlas_json)?,
+            OutputType::Ron => save_output::<RonOutput>(file_path, atlas_json)?,
+            OutputType::Toml => save_output::<TomlOutput>(file_path, atlas_json)?,
+            OutputType::Godot => save_output::<GodotOutput>(file_path, atlas_json)?,
         }
+    }
+
+    Ok(())
+}
 
-        if config.allow_normal_output {
-            match config.output_type {
-                OutputType::Json => save_output::<JsonOutput>(file_path, atlas_json)?,
-                OutputType::Binary => save_output_from(
-                    BinaryOutput(&config), file_path, atlas_json)?,
-                OutputType::Ron => save_output::<RonOutput>(file_path, atlas_json)?,
-                OutputType::Toml => save_output::<TomlOutput>(file_path, atlas_json)?,
+/// The path of a page without its extension.
+fn page_path(config: &Config, input_path: &Option<PathBuf>, index: usize, page_count: usize) -> PathBuf {
+    let mut path = config.fixed_output_path(input_path);
+    if page_count == 1 {
+        path.push(&config.name);
+    } else {
+        path.push(format!("{}_{}", config.name, index));
+    }
+    path
+}
+
+/// Repeats the edge pixels of the image `amount` times outward.
+fn extrude_edges(img: &RgbaImage, amount: u32) -> RgbaImage {
+    let (width, height) = img.dimensions();
+    if amount == 0 || width == 0 || height == 0 {
+        return img.clone();
+    }
+    ImageBuffer::from_fn(width + amount * 2, height + amount * 2, |x, y| {
+        let x = x.saturating_sub(amount).min(width - 1);
+        let y = y.saturating_sub(amount).min(height - 1);
+        *img.get_pixel(x, y)
+    })
+}
+
+type Page<'a> = (usize, usize, Vec<(crunch::Rect, &'a ImageTexture)>);
+
+/// Packs the images with the configured `packer`. In `Best` mode every
+/// algorithm is tried, keeping the fewest pages and then the smallest area.
+fn pack_pages<'a>(
+    images: &'a [ImageTexture],
+    options: &ImageOptions
+) -> Result<(Vec<Page<'a>>, PackReport), PackerError> {
+    let candidates = Algorithm::candidates(options.packer, options.heuristic);
+    let mut report = PackReport::default();
+    if candidates.len() == 1 {
+        let pages = pack_pages_with(images, options, candidates[0])?;
+        report.packer = score(candidates[0], &pages, options);
+        return Ok((pages, report));
+    }
+
+    let mut best: Option<(PackerScore, Vec<Page<'a>>)> = None;
+    let mut last_error = PackerError::FailedToPacked;
+    for algorithm in candidates {
+        let pages = match pack_pages_with(images, options, algorithm) {
+            Ok(pages) => pages,
+            Err(err) => {
+                report.failed_packers.push((algorithm.to_string(), err.to_string()));
+                last_error = err;
+                continue;
             }
+        };
+        let score = score(algorithm, &pages, options);
+        report.candidates.push(score.clone());
+        let size = (score.pages, score.area);
+        if best.as_ref().is_none_or(|(best, _)| size < (best.pages, best.area)) {
+            best = Some((score, pages));
         }
+    }
 
-        Ok(())
+    // When every packer failed, the reason of the last one is as good as any.
+    let Some((packer, pages)) = best else {
+        return Err(last_error);
+    };
+    report.packer = packer;
+    Ok((pages, report))
+}
+
+fn score(algorithm: Algorithm, pages: &[Page], options: &ImageOptions) -> PackerScore {
+    let spacing = (options.extrude * 2 + options.padding) as usize;
+    let area: usize = pages.iter().map(|(w, h, _)| w * h).sum();
+    let used_area: usize = pages.iter()
+        .flat_map(|(_, _, packed)| packed.iter())
+        .map(|(rect, _)| (rect.w - spacing) * (rect.h - spacing))
+        .sum();
+    PackerScore {
+        name: algorithm.to_string(),
+        pages: pages.len(),
+        area,
+        occupancy: used_area as f64 * 100.0 / area.max(1) as f64
+    }
+}
+
+fn pack_pages_with<'a>(
+    images: &'a [ImageTexture],
+    options: &ImageOptions,
+    algorithm: Algorithm
+) -> Result<Vec<Page<'a>>, PackerError> {
+    let (page_width, page_height) = options.page_size();
+    let rotation = if options.allow_rotation {
+        Rotation::Allowed
     } else {
-        Err(PackerError::FailedToPacked)?
+        Rotation::None
+    };
+    let spacing = (options.extrude * 2 + options.padding) as usize;
+    let to_items = |indices: &[usize]| indices.iter().map(|&i| {
+        let img = &images[i].img;
+        Item::new(i, img.width() as usize + spacing, img.height() as usize + spacing, rotation)
+    }).collect::<Vec<Item<usize>>>();
+    let pack = |area: crunch::Rect, indices: &[usize]| {
+        RectPacker::new(algorithm, to_items(indices)).pack(area)
+    };
+    let to_page = |(w, h, packed): Sheet| {
+        (w, h, packed.into_iter().map(|(rect, i)| (rect, &images[i])).collect())
+    };
+
+    let mut pages = vec![];
+    let mut remaining: Vec<usize> = (0..images.len()).collect();
+    sort_images(images, &mut remaining, options.sort_by);
+
+    while !remaining.is_empty() {
+        if let Some(page) = pack_into_sheet(options, RectPacker::new(algorithm, to_items(&remaining))) {
+            pages.push(to_page(page));
+            break;
+        }
+
+        // Everything does not fit into one sheet, so fill a whole sheet and
+        // leave the rest for the next page.
+        let Some(full_size) = options.packing_area(page_width, page_height) else {
+            return Err(too_large(images, &remaining, options));
+        };
+        let (Ok(mut placed) | Err(mut placed)) = pack(full_size, &remaining);
+        let mut packed: Vec<usize> = placed.iter().map(|&(_, i)| i).collect();
+        if packed.is_empty() {
+            return Err(too_large(images, &remaining, options));
+        }
+        remaining.retain(|i| !packed.contains(i));
+
+        // crunch stops at the first item that does not fit, so try to fill
+        // the leftover space with the smaller ones. Every try packs the page
+        // again, so stop as soon as nothing can fit anymore, and skip the
+        // images that are at least as large as one that did not fit.
+        let size = |i: &usize| {
+            let img = &images[*i].img;
+            (img.width() as usize + spacing, img.height() as usize + spacing)
+        };
+        let area = |i: &usize| size(i).0 * size(i).1;
+        let smallest = remaining.iter().map(area).min().unwrap_or_default();
+        let mut free_area = full_size.area().saturating_sub(packed.iter().map(area).sum());
+        let mut failed: Vec<(usize, usize)> = vec![];
+        remaining.retain(|i| {
+            let (width, height) = size(i);
+            if free_area < smallest
+                || area(i) > free_area
+                || failed.iter().any(|&(w, h)| width >= w && height >= h) {
+                return true;
+            }
+            packed.push(*i);
+            match pack(full_size, &packed) {
+                Ok(new_placed) => placed = new_placed,
+                Err(_) => {
+                    packed.pop();
+                    failed.push((width, height));
+                    return true;
+                }
+            }
+            free_area -= area(i);
+            false
+        });
+
+        // Packing the same items in another order may place them differently
+        // and fail, so fall back to the last placement that worked.
+        let page = pack_into_sheet(options, RectPacker::new(algorithm, to_items(&packed)))
+            .unwrap_or((page_width, page_height, placed));
+        pages.push(to_page(page));
+    }
+
+    Ok(pages)
+}
+
+/// The error for images that do not fit into an empty sheet, naming the
+/// first image that is too large on its own.
+fn too_large(images: &[ImageTexture], indices: &[usize], options: &ImageOptions) -> PackerError {
+    let (page_width, page_height) = options.page_size();
+    let spacing = (options.extrude * 2 + options.padding) as usize;
+    let area = options.packing_area(page_width, page_height);
+    let fits = |width: usize, height: usize| area.is_some_and(|area| {
+        (width <= area.w && height <= area.h)
+            || (options.allow_rotation && height <= area.w && width <= area.h)
+    });
+    indices
+        .iter()
+        .map(|&i| &images[i])
+        .find(|image| !fits(image.img.width() as usize + spacing, image.img.height() as usize + spacing))
+        .map_or(PackerError::FailedToPacked, |image| PackerError::ImageTooLarge {
+            name: image.name.clone(),
+            width: image.img.width(),
+            height: image.img.height()
+        })
+}
+
+/// Orders the images from the largest to the smallest by the key, or by
+/// name. Ties keep the order of the names.
+fn sort_images(images: &[ImageTexture], indices: &mut [usize], sort_by: SortBy) {
+    let key = |i: &usize| {
+        let (w, h) = images[*i].img.dimensions();
+        match sort_by {
+            SortBy::Area => w as u64 * h as u64,
+            SortBy::Height => h as u64,
+            SortBy::Width => w as u64,
+            SortBy::Perimeter => 2 * (w as u64 + h as u64),
+            SortBy::Name => 0
+        }
+    };
+    indices.sort_by(|a, b| key(b).cmp(&key(a)).then_with(|| images[*a].name.cmp(&images[*b].name)));
+}
+
+type Sheet = (usize, usize, Placed);
+
+/// Packs the items into the smallest sheet allowed by the `size_mode`.
+fn pack_into_sheet(options: &ImageOptions, mut packer: RectPacker) -> Option<Sheet> {
+    match options.size_mode {
+        SizeMode::PowerOfTwo => pack_into_po2(options, packer),
+        SizeMode::Square => pack_into_square(options, packer),
+        SizeMode::Fixed { width, height } => {
+            let area = options.packing_area(width, height)?;
+            packer.pack(area).ok().map(|packed| (width, height, packed))
+        }
+        SizeMode::Any => {
+            // Cut the unused space off the smallest square that fits everything.
+            let (_, _, packed) = pack_into_square(options, packer)?;
+            let (padding, border) = (options.padding as usize, options.border as usize);
+            let right = packed.iter().map(|(rect, _)| rect.right()).max().unwrap_or(0);
+            let bottom = packed.iter().map(|(rect, _)| rect.bottom()).max().unwrap_or(0);
+            Some((
+                (right + border).saturating_sub(padding).max(1),
+                (bottom + border).saturating_sub(padding).max(1),
+                packed
+            ))
+        }
     }
 }
 
+/// Packs the items into the smallest square sheet that does not exceed the `max_size`.
+fn pack_into_square(options: &ImageOptions, mut packer: RectPacker) -> Option<Sheet> {
+    let mut sheet = None;
+    let (mut low, mut high) = (1, options.max_size);
+    while low <= high {
+        let size = (low + high) / 2;
+        match options.packing_area(size, size).and_then(|area| packer.pack(area).ok()) {
+            Some(packed) => {
+                sheet = Some((size, size, packed));
+                high = size - 1;
+            }
+            None => low = size + 1
+        }
+    }
+    sheet
+}
+
+/// Packs the items into the smallest power of two sheet that does not exceed
+/// the `max_size`, leaving room for the border of the sheet.
+fn pack_into_po2(options: &ImageOptions, mut packer: RectPacker) -> Option<Sheet> {
+    let min_area = packer.min_area();
+
+    let mut size = 2;
+    while size <= options.max_size {
+        for (w, h) in [(size, size), (size * 2, size), (size, size * 2)] {
+            if w > options.max_size || h > options.max_size {
+                continue;
+            }
+            let Some(area) = options.packing_area(w, h) else { continue };
+            if area.area() < min_area {
+                continue;
+            }
+            if let Ok(packed) = packer.pack(area) {
+                return Some((w, h, packed));
+            }
+        }
+        size *= 2;
+    }
+
+    None
+}
+
 struct AseItem {
     row: u32,
     column: u32,
     frame: u32
 }
 
-fn process_ase(ase: AsepriteFile, filename: String, nine_patch: Option<Rect>, one_frame: bool) -> anyhow::Result<Vec<ImageTexture>> {
+fn process_ase(
+    ase: AsepriteFile,
+    filename: String,
+    nine_patch: Option<Rect>,
+    features: &Features
+) -> anyhow::Result<(Vec<ImageTexture>, Vec<Animation>)> {
+    let layers = select_ase_layers(&ase, features)?;
+
+    if features.ase_split_layers {
+        let mut images = vec![];
+        let mut animations = vec![];
+        for layer in layers {
+            let layer = ase.layer(layer);
+            let (mut layer_images, mut layer_animations) = process_ase_frames(
+                &ase,
+                format!("{}/{}", filename, layer.name()),
+                nine_patch,
+                features.ase_sheet,
+                |frame| layer.frame(frame).image()
+            );
+            images.append(&mut layer_images);
+            animations.append(&mut layer_animations);
+        }
+        return Ok((images, animations));
+    }
+
+    let filtered = filters_ase_layers(features);
+    Ok(process_ase_frames(&ase, filename, nine_patch, features.ase_sheet, |frame| {
+        if !filtered {
+            return ase.frame(frame).image();
+        }
+        // Aseprite only flattens the visible layers, so the selected layers are flattened by
+        // hand. They are stacked in Normal mode, see `ase_blend_warning`.
+        let mut image = RgbaImage::new(ase.width() as u32, ase.height() as u32);
+        for &layer in layers.iter() {
+            image::imageops::overlay(&mut image, &ase.layer(layer).frame(frame).image(), 0, 0);
+        }
+        image
+    }))
+}
+
+/// Whether the features select the layers of the flattened frames.
+fn filters_ase_layers(features: &Features) -> bool {
+    !features.ase_include_layers.is_empty()
+        || !features.ase_exclude_layers.is_empty()
+        || !features.ase_ignore_hidden
+}
+
+/// Names the selected layers whose blend mode is lost when the frames are
+/// flattened by hand. The bottom layer is blended onto a transparent image
+/// either way, so only the layers above it count.
+fn ase_blend_warning(ase: &AsepriteFile, features: &Features) -> anyhow::Result<Option<String>> {
+    if features.ase_split_layers || !filters_ase_layers(features) {
+        return Ok(None);
+    }
+    let blended: Vec<String> = select_ase_layers(ase, features)?
+        .into_iter()
+        .skip(1)
+        .map(|layer| ase.layer(layer))
+        .filter(|layer| !matches!(layer.blend_mode(), BlendMode::Normal))
+        .map(|layer| layer.name().to_owned())
+        .collect();
+    if blended.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(format!(
+        "the layer(s) {} are flattened in Normal mode instead of their blend mode",
+        blended.join(", ")
+    )))
+}
+
+/// Lists the layers that pass the filters of the features, from the bottom to the top.
+fn select_ase_layers(ase: &AsepriteFile, features: &Features) -> anyhow::Result<Vec<u32>> {
+    let include = glob_set(&features.ase_include_layers)?;
+    let exclude = glob_set(&features.ase_exclude_layers)?;
+
+    Ok(ase.layers()
+        .filter(|layer| !matches!(layer.layer_type(), LayerType::Group))
+        .filter(|layer| !features.ase_ignore_hidden || layer.is_visible())
+        .filter(|layer| features.ase_include_layers.is_empty() || include.is_match(layer.name()))
+        .filter(|layer| !exclude.is_match(layer.name()))
+        .map(|layer| layer.id())
+        .collect())
+}
+
+fn process_ase_frames(
+    ase: &AsepriteFile,
+    filename: String,
+    nine_patch: Option<Rect>,
+    one_frame: bool,
+    frame_image: impl Fn(u32) -> RgbaImage
+) -> (Vec<ImageTexture>, Vec<Animation>) {
     let frames = ase.num_frames();
 
     let mut images = vec![];
 
     if frames == 1 {
-        let img = ase.frame(0).image();
-        images.push(ImageTexture::new(filename, img, nine_patch));
-        return Ok(images);
+        let img = frame_image(0);
+        let animations = ase_animations(ase, &filename, |_| filename.clone());
+        images.push(ImageTexture::new(filename, img, nine_patch).with_slices(ase_slices(ase, 0)));
+        return (images, animations);
     }
     if one_frame {
         let iw = ase.width() as u32;
@@ -412,20 +1739,86 @@ fn process_ase(ase: AsepriteFile, filename: String, nine_patch: Option<Rect>, on
         );
 
         for a in ases {
-            let cel = ase.frame(a.frame);
-            let img = cel.image().view(0, 0, iw, ih).to_image();
+            let img = frame_image(a.frame).view(0, 0, iw, ih).to_image();
             texture.copy_from(&img, a.row * iw, a.column * ih).unwrap();
         }
 
-        images.push(ImageTexture::new(filename, texture, nine_patch))
+        // The cells are a single frame, so there is nothing the tags could point at.
+        images.push(ImageTexture::new(filename, texture, nine_patch));
+        (images, vec![])
     } else {
         for i in 0..frames {
-            let cel = ase.frame(i);
-            images.push(ImageTexture::new(format!("{}/{}", filename, i.to_string()), cel.image(), nine_patch))
+            images.push(
+                ImageTexture::new(format!("{}/{}", filename, i), frame_image(i), nine_patch)
+                    .with_slices(ase_slices(ase, i))
+            )
         }
+
+        let animations = ase_animations(ase, &filename, |i| format!("{}/{}", filename, i));
+        (images, animations)
     }
+}
 
-    Ok(images)
+/// Lists the slices of the Aseprite file at the given frame, with the nine patch
+/// and the pivot moved from the bounds of the slice to the frame.
+fn ase_slices(ase: &AsepriteFile, frame: u32) -> Vec<Slice> {
+    ase.slices().iter().filter_map(|slice| {
+        // A key is valid until the next key starts.
+        let key = slice.keys.iter()
+            .filter(|key| key.from_frame <= frame)
+            .max_by_key(|key| key.from_frame)?;
+        let (x, y) = key.origin;
+        let nine_patch = key.slice9.as_ref().map(|center| Rect {
+            x: (x + center.center_x).max(0) as u32,
+            y: (y + center.center_y).max(0) as u32,
+            w: center.center_width,
+            h: center.center_height
+        });
+        let pivot = key.pivot.map(|(px, py)| Point { x: x + px, y: y + py });
+        Some(Slice {
+            name: slice.name.clone(),
+            x, y,
+            w: key.size.0, h: key.size.1,
+            nine_patch,
+            pivot
+        })
+    }).collect()
+}
+
+/// Lists every tag of the Aseprite file as an animation, or the whole file if
+/// it has no tags but several frames. A static sprite has no animation.
+fn ase_animations(
+    ase: &AsepriteFile,
+    filename: &str,
+    frame_name: impl Fn(u32) -> String
+) -> Vec<Animation> {
+    let animation = |name: String, from: u32, to: u32, direction| Animation {
+        name,
+        direction,
+        frames: (from..=to).map(|i| AnimationFrame {
+            name: frame_name(i),
+            duration: ase.frame(i).duration()
+        }).collect()
+    };
+
+    if ase.num_tags() == 0 {
+        if ase.num_frames() == 1 {
+            return vec![];
+        }
+        return vec![
+            animation(filename.to_owned(), 0, ase.num_frames() - 1, AnimationDirection::Forward)
+        ];
+    }
+
+    (0..ase.num_tags()).map(|i| {
+        let tag = ase.tag(i);
+        animation(
+            format!("{}/{}", filename, tag.name()),
+            tag.from_frame(),
+            tag.to_frame(),
+            tag.animation_direction().into()
+        )
+    }).collect()
 }
 
 #[derive(serde::Serialize)]
@@ -433,3 +1826,55 @@ struct TemplateGlobals {
     atlas: PackerAtlas,
     config: Config
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_written_files_are_outputs() {
+        let folder = std::env::temp_dir().join(format!("cluttered-outputs-{}", std::process::id()));
+        let input_path = Some(folder.join("cfg.toml"));
+        let config = |output_type: &str| -> Config {
+            toml::from_str(&format!("name = \"hero\"\noutput_path = \".\"\nfolders = []\noutput_type = \"{output_type}\"")).unwrap()
+        };
+        let is_output = |config: &Config, path: &str| config.is_output(&input_path, &folder.join(path));
+
+        let json = config("Json");
+        for path in ["hero.json", "hero.png", "hero_2.png", "hero.cache.json"] {
+            assert!(is_output(&json, path), "{path}");
+        }
+        for path in ["hero/idle.png", "hero.ron", "hero_x.png", "hero.txt", "other.png", "hero/hero.png"] {
+            assert!(!is_output(&json, path), "{path}");
+        }
+
+        let godot = config("Godot");
+        for path in ["hero/idle.tres", "hero/run/0.tres", "hero.tres", "hero.png"] {
+            assert!(is_output(&godot, path), "{path}");
+        }
+        for path in ["hero/idle.png", "hero.json", "other/idle.tres"] {
+            assert!(!is_output(&godot, path), "{path}");
+        }
+    }
+
+    #[test]
+    fn fills_pages_with_images_that_only_fit_one_by_one() {
+        let sizes = [
+            (36, 28), (39, 29), (25, 33), (22, 5), (27, 35), (36, 12), (20, 6), (36, 33),
+            (35, 13), (34, 28), (31, 27), (34, 3), (32, 3), (13, 29), (13, 35)
+        ];
+        let images = sizes.iter().enumerate().map(|(i, &(w, h))| {
+            let img = ImageBuffer::from_pixel(w, h, Rgba([i as u8, 0, 0, 255]));
+            ImageTexture::new(format!("{i:02}"), img, None)
+        }).collect();
+        let options = ImageOptions { max_size: 64, ..Default::default() };
+
+        let packed = pack_images(images, vec![], &options).unwrap();
+        assert_eq!(packed.atlas.frames.len(), sizes.len());
+        for (name, data) in packed.atlas.frames.iter() {
+            let (w, h) = sizes[name.parse::<usize>().unwrap()];
+            assert_eq!((data.width, data.height), (w, h), "{name}");
+            assert!(data.x + data.width <= 64 && data.y + data.height <= 64, "{name}");
+        }
+    }
+}
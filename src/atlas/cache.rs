@@ -0,0 +1,124 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use serde_json as json;
+use xxhash_rust::xxh3::xxh3_64;
+
+use super::{Config, page_path, source_files};
+
+/// What the last pack of a config was made from, stored next to its output
+/// as `<name>.cache.json`.
+#[derive(serde::Deserialize, serde::Serialize)]
+pub(super) struct CacheManifest {
+    /// The version of cluttered, as a different version may pack differently.
+    version: String,
+    /// The hash of the config.
+    config: String,
+    /// Every image and template, by path.
+    inputs: BTreeMap<PathBuf, Input>,
+    /// Every file written by the pack.
+    #[serde(default)]
+    outputs: Vec<PathBuf>
+}
+
+/// An input file, with the size and the modification time it was hashed at.
+#[derive(serde::Deserialize, serde::Serialize)]
+struct Input {
+    len: u64,
+    /// Seconds and nanoseconds since the Unix epoch.
+    modified: (u64, u32),
+    hash: String
+}
+
+impl Input {
+    /// Reads and hashes the file, unless its size and modification time are
+    /// the same as in the last manifest.
+    fn new(path: &Path, last: Option<&Input>) -> anyhow::Result<Input> {
+        let metadata = std::fs::metadata(path)?;
+        let modified = metadata.modified()?.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let modified = (modified.as_secs(), modified.subsec_nanos());
+        let len = metadata.len();
+        let hash = match last {
+            Some(last) if last.len == len && last.modified == modified => last.hash.clone(),
+            _ => hash(&std::fs::read(path)?)
+        };
+        Ok(Input { len, modified, hash })
+    }
+}
+
+impl CacheManifest {
+    /// Describes the current inputs. Only the files that changed size or
+    /// modification time since the `last` manifest are read again.
+    pub(super) fn new(
+        config: &Config,
+        input_path: &Option<PathBuf>,
+        last: Option<&CacheManifest>
+    ) -> anyhow::Result<CacheManifest> {
+        let mut inputs = BTreeMap::new();
+        let paths = source_files(config, input_path)?
+            .into_iter()
+            .chain(config.template_paths(input_path));
+        for path in paths {
+            let input = Input::new(&path, last.and_then(|last| last.inputs.get(&path)))?;
+            inputs.insert(path, input);
+        }
+
+        Ok(CacheManifest {
+            version: env!("CARGO_PKG_VERSION").into(),
+            config: hash(&json::to_vec(config)?),
+            inputs,
+            outputs: vec![]
+        })
+    }
+
+    /// Records the files written for `page_count` pages.
+    pub(super) fn with_outputs(
+        mut self,
+        config: &Config,
+        input_path: &Option<PathBuf>,
+        page_count: usize
+    ) -> CacheManifest {
+        let extension = config.image_options.output_extension.extension();
+        for index in 0..page_count {
+            self.outputs.push(page_path(config, input_path, index, page_count).with_extension(extension));
+        }
+
+        self.outputs.extend(config.output_files(input_path));
+        self
+    }
+
+    /// Reads the manifest of the last pack, if there is a readable one.
+    pub(super) fn load(config: &Config, input_path: &Option<PathBuf>) -> Option<CacheManifest> {
+        let buffer = std::fs::read(manifest_path(config, input_path)).ok()?;
+        json::from_slice(&buffer).ok()
+    }
+
+    pub(super) fn save(&self, config: &Config, input_path: &Option<PathBuf>) -> anyhow::Result<()> {
+        let buffer = json::to_string_pretty(self)?;
+        std::fs::write(manifest_path(config, input_path), buffer)?;
+        Ok(())
+    }
+
+    /// Whether packing again would produce the same output as this manifest
+    /// describes, given the `current` inputs, and that output still exists.
+    pub(super) fn is_up_to_date(&self, current: &CacheManifest) -> bool {
+        self.version == current.version
+            && self.config == current.config
+            && self.inputs.len() == current.inputs.len()
+            && self.inputs.iter().zip(current.inputs.iter())
+                .all(|((path, input), (current_path, current))| path == current_path && input.hash == current.hash)
+            && !self.outputs.is_empty()
+            && self.outputs.iter().all(|path| path.exists())
+    }
+}
+
+fn manifest_path(config: &Config, input_path: &Option<PathBuf>) -> PathBuf {
+    let mut path = config.fixed_output_path(input_path);
+    path.push(format!("{}.cache.json", config.name));
+    path
+}
+
+fn hash(bytes: &[u8]) -> String {
+    format!("{:016x}", xxh3_64(bytes))
+}
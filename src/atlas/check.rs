@@ -0,0 +1,195 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use super::{Config, source_files, packed_extension, check_output};
+use crate::error::PackerError;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning
+}
+
+/// A problem found by `check`.
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub file: PathBuf,
+    /// The line and the column, starting at 1, when they are known.
+    pub position: Option<(usize, usize)>,
+    pub message: String
+}
+
+impl Diagnostic {
+    fn error(file: &Path, message: String) -> Self {
+        Diagnostic { severity: Severity::Error, file: file.to_owned(), position: None, message }
+    }
+
+    fn warning(file: &Path, message: String) -> Self {
+        Diagnostic { severity: Severity::Warning, file: file.to_owned(), position: None, message }
+    }
+
+    /// Points at the position of the errors that have one.
+    fn from_error(file: &Path, err: anyhow::Error) -> Self {
+        match err.downcast::<PackerError>() {
+            Ok(PackerError::ConfigSyntax { file, line, column, message }) => Diagnostic {
+                severity: Severity::Error,
+                file,
+                position: Some((line, column)),
+                message
+            },
+            Ok(PackerError::InvalidConfig(message)) => Diagnostic::error(file, message),
+            Ok(err) => Diagnostic::error(file, err.to_string()),
+            Err(err) => Diagnostic::error(file, err.to_string())
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning"
+        };
+        match self.position {
+            Some((line, column)) => write!(
+                f, "{}:{}:{}: {}: {}", self.file.display(), line, column, severity, self.message),
+            None => write!(f, "{}: {}: {}", self.file.display(), severity, self.message)
+        }
+    }
+}
+
+/// Reads the config file with `Config::all_from_path` and looks for
+/// everything that would fail or be surprising when packing it, without
+/// packing anything.
+pub fn check(path: &Path) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+    let configs = match Config::all_from_path(path) {
+        Ok(configs) => configs,
+        Err(err) => {
+            diagnostics.push(Diagnostic::from_error(path, err));
+            return diagnostics;
+        }
+    };
+
+    for config in configs.iter() {
+        check_config(config, path, &mut diagnostics);
+    }
+    diagnostics
+}
+
+fn check_config(config: &Config, path: &Path, diagnostics: &mut Vec<Diagnostic>) {
+    let input_path = &Some(path.to_owned());
+    let name = &config.name;
+
+    let mut folders_exist = true;
+    for (folder, folder_path) in config.folders.iter().zip(config.folder_paths(input_path)) {
+        if !folder_path.is_dir() {
+            folders_exist = false;
+            diagnostics.push(Diagnostic::error(
+                path, format!("atlas `{name}`: the folder {} does not exist", folder_path.display())));
+        }
+        if let Err(err) = folder.filter() {
+            folders_exist = false;
+            diagnostics.push(Diagnostic::error(
+                path, format!("atlas `{name}`: invalid pattern for {}: {err}", folder_path.display())));
+        }
+    }
+
+    for template_path in config.template_paths(input_path) {
+        if !template_path.is_file() {
+            diagnostics.push(Diagnostic::error(
+                path, format!("atlas `{name}`: the template {} does not exist", template_path.display())));
+        }
+    }
+
+    if let Err(err) = check_output(config) {
+        diagnostics.push(Diagnostic::error(path, format!("atlas `{name}`: {err}")));
+    }
+
+    let output_path = config.fixed_output_path(input_path);
+    if output_path.exists() && !output_path.is_dir() {
+        diagnostics.push(Diagnostic::error(
+            path, format!("atlas `{name}`: the output path {} is not a folder", output_path.display())));
+    }
+
+    if !folders_exist {
+        return;
+    }
+    match source_files(config, input_path) {
+        Ok(files) => {
+            let images = files.iter().filter(|file| packed_extension(config, file).is_some()).count();
+            if images == 0 {
+                diagnostics.push(Diagnostic::warning(
+                    path, format!("atlas `{name}`: no images match in the folders")));
+            }
+        }
+        Err(err) => diagnostics.push(Diagnostic::error(path, format!("atlas `{name}`: {err}")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check_file(test: &str, name: &str, content: &str) -> Vec<Diagnostic> {
+        let dir = std::env::temp_dir().join(format!("cluttered-check-{test}-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("sprites")).unwrap();
+        std::fs::write(dir.join(name), content).unwrap();
+        let diagnostics = check(&dir.join(name));
+        std::fs::remove_dir_all(dir).unwrap();
+        diagnostics
+    }
+
+    #[test]
+    fn unknown_folder_key_points_at_its_line_and_column() {
+        let diagnostics = check_file("folder-key", "config.toml", concat!(
+            "name = \"sprites\"\n",
+            "output_path = \"out\"\n",
+            "folders = [\n",
+            "  { path = \"sprites\", exlude = [\"*.psd\"] },\n",
+            "]\n"
+        ));
+        assert_eq!(diagnostics.len(), 1);
+        let diagnostic = &diagnostics[0];
+        assert!(diagnostic.severity == Severity::Error);
+        assert_eq!(diagnostic.position, Some((4, 3)));
+        assert!(diagnostic.message.contains("unknown field `exlude`"), "{}", diagnostic.message);
+    }
+
+    #[test]
+    fn unknown_key_is_an_error_in_every_format() {
+        let files = [
+            ("config.toml", "name = \"sprites\"\noutput_path = \"out\"\nfolders = [\"sprites\"]\n[image_options]\nmax_sise = 64\n"),
+            ("config.json", "{\n  \"name\": \"sprites\",\n  \"output_path\": \"out\",\n  \"folders\": [\"sprites\"],\n  \"image_options\": { \"max_sise\": 64 }\n}"),
+            ("config.ron", "(\n    name: \"sprites\",\n    output_path: \"out\",\n    folders: [\"sprites\"],\n    image_options: (max_sise: 64),\n)")
+        ];
+        for (name, content) in files {
+            let diagnostics = check_file("unknown-key", name, content);
+            assert_eq!(diagnostics.len(), 1, "{name}");
+            let diagnostic = &diagnostics[0];
+            assert!(diagnostic.severity == Severity::Error, "{name}");
+            assert_eq!(diagnostic.position.map(|(line, _)| line), Some(5), "{name}");
+            assert!(diagnostic.message.contains("`max_sise`"), "{name}: {}", diagnostic.message);
+        }
+    }
+
+    #[test]
+    fn syntax_error_points_at_its_line_and_column() {
+        let diagnostics = check_file("syntax", "config.json", "{\n  \"name\": \"sprites\",\n  \"folders\": [\"sprites\"\n}");
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0].severity == Severity::Error);
+        assert_eq!(diagnostics[0].position, Some((4, 1)));
+    }
+
+    #[test]
+    fn missing_folder_is_an_error() {
+        let diagnostics = check_file("missing", "config.toml", concat!(
+            "name = \"sprites\"\n",
+            "output_path = \"out\"\n",
+            "folders = [\"sprites\", \"missing\"]\n"
+        ));
+        let errors: Vec<&Diagnostic> = diagnostics.iter().filter(|x| x.severity == Severity::Error).collect();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].message.contains("missing does not exist"), "{}", errors[0].message);
+    }
+}
@@ -0,0 +1,542 @@
+use std::path::{Path, PathBuf};
+
+use serde::de::DeserializeOwned;
+
+use super::{
+    Config, Folder, TemplatePath, OutputType, ImageOptions, BinaryOptions, Features,
+    OutputExtensionType, SizeMode, FrameOrder, PackingAlgorithm, MaxRectsHeuristic, SortBy,
+    Endianness, CoordinateWidth, StringEncoding, default_extensions, default_allow_normal_output
+};
+use crate::error::PackerError;
+
+/// A config file, describing a single atlas with its settings at the top
+/// level, or several `atlas` entries that take the settings they do not set
+/// from the top level.
+///
+/// Every setting is optional, so the entries and the configs they extend can
+/// be laid over each other before the defaults fill in the rest. A key that
+/// is not a setting is an error, pointing at where it is.
+#[derive(serde::Deserialize, Default, Clone)]
+#[serde(default, deny_unknown_fields, rename = "Config")]
+pub(super) struct ConfigLayer {
+    /// A config file to take the settings from that are not set, relative to this file.
+    extends: Option<PathBuf>,
+    /// Every config file that was extended, directly or through another one.
+    #[serde(skip)]
+    extended_from: Vec<PathBuf>,
+    name: Option<String>,
+    output_path: Option<PathBuf>,
+    folders: Option<Vec<Folder>>,
+    extensions: Option<Vec<String>>,
+    allow_normal_output: Option<bool>,
+    template_path: Option<TemplatePath>,
+    strict: Option<bool>,
+    output_type: Option<OutputType>,
+    image_options: Option<ImageOptionsLayer>,
+    binary_options: Option<BinaryOptionsLayer>,
+    features: Option<FeaturesLayer>,
+    atlas: Vec<ConfigLayer>
+}
+
+#[derive(serde::Deserialize, Default, Clone)]
+#[serde(default, deny_unknown_fields, rename = "ImageOptions")]
+struct ImageOptionsLayer {
+    output_extension: Option<OutputExtensionType>,
+    max_size: Option<usize>,
+    show_extension: Option<bool>,
+    size_mode: Option<SizeMode>,
+    trim: Option<bool>,
+    allow_rotation: Option<bool>,
+    padding: Option<u32>,
+    border: Option<u32>,
+    extrude: Option<u32>,
+    remove_duplicates: Option<bool>,
+    show_aliases: Option<bool>,
+    frame_order: Option<FrameOrder>,
+    packer: Option<PackingAlgorithm>,
+    heuristic: Option<MaxRectsHeuristic>,
+    sort_by: Option<SortBy>
+}
+
+#[derive(serde::Deserialize, Default, Clone)]
+#[serde(default, deny_unknown_fields, rename = "BinaryOptions")]
+struct BinaryOptionsLayer {
+    endianness: Option<Endianness>,
+    coordinate_width: Option<CoordinateWidth>,
+    string_encoding: Option<StringEncoding>
+}
+
+#[derive(serde::Deserialize, Default, Clone)]
+#[serde(default, deny_unknown_fields, rename = "Features")]
+struct FeaturesLayer {
+    nine_patch: Option<bool>,
+    aseprite: Option<bool>,
+    ase_sheet: Option<bool>,
+    ase_split_layers: Option<bool>,
+    ase_include_layers: Option<Vec<String>>,
+    ase_exclude_layers: Option<Vec<String>>,
+    ase_ignore_hidden: Option<bool>
+}
+
+/// What is tracked while reading a config file and the files it extends.
+#[derive(Default)]
+pub(super) struct ConfigReader {
+    /// The files that are being read, to catch a file that ends up extending itself.
+    chain: Vec<PathBuf>
+}
+
+/// Lays `over` on top of `base`, merging both when they are set.
+fn merge<T>(over: Option<T>, base: Option<T>, merge: impl FnOnce(T, T) -> T) -> Option<T> {
+    match (over, base) {
+        (Some(over), Some(base)) => Some(merge(over, base)),
+        (over, base) => over.or(base)
+    }
+}
+
+impl ConfigLayer {
+    /// Reads the config file with the format matching the extension of the
+    /// path, together with the configs it extends.
+    pub(super) fn from_path(path: &Path, reader: &mut ConfigReader) -> anyhow::Result<ConfigLayer> {
+        let extension = path
+            .extension()
+            .and_then(std::ffi::OsStr::to_str)
+            .unwrap_or_default();
+        ConfigLayer::load(path, extension, reader)
+    }
+
+    /// Reads the config file in the given format, together with the configs it extends.
+    pub(super) fn from_path_as(path: &Path, extension: &str) -> anyhow::Result<ConfigLayer> {
+        ConfigLayer::load(path, extension, &mut ConfigReader::default())
+    }
+
+    fn load(path: &Path, extension: &str, reader: &mut ConfigReader) -> anyhow::Result<ConfigLayer> {
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.to_owned());
+        if reader.chain.contains(&canonical) {
+            Err(PackerError::InvalidConfig(format!("{} ends up extending itself", path.display())))?
+        }
+
+        reader.chain.push(canonical);
+        let layer = read_config_file::<ConfigLayer>(path, extension)?;
+        let layer = layer
+            .expand_env()?
+            .extend(path.parent().unwrap_or(Path::new("")), reader)?;
+        reader.chain.pop();
+        Ok(layer)
+    }
+
+    /// Lays this layer and its atlases over the configs they extend. `dir` is
+    /// the folder of the file declaring them.
+    fn extend(mut self, dir: &Path, reader: &mut ConfigReader) -> anyhow::Result<ConfigLayer> {
+        self.atlas = self.atlas
+            .into_iter()
+            .map(|entry| entry.extend(dir, reader))
+            .collect::<anyhow::Result<_>>()?;
+
+        let Some(extends) = self.extends.take() else {
+            return Ok(self);
+        };
+        let path = dir.join(&extends);
+        if !path.is_file() {
+            Err(PackerError::InvalidConfig(format!("the extended config {} does not exist", path.display())))?
+        }
+        let extension = path
+            .extension()
+            .and_then(std::ffi::OsStr::to_str)
+            .unwrap_or_default()
+            .to_owned();
+        let mut parent = ConfigLayer::load(&path, &extension, reader)?
+            .relative_to(extends.parent().unwrap_or(Path::new("")));
+        parent.extended_from.insert(0, path);
+        Ok(self.or(parent))
+    }
+
+    /// Makes the relative paths of the layer relative to the folder one level up.
+    fn relative_to(mut self, dir: &Path) -> ConfigLayer {
+        self.output_path = self.output_path.map(|path| dir.join(path));
+        self.folders = self.folders.map(|folders| folders.into_iter().map(|folder| match folder {
+            Folder::Path(path) => Folder::Path(dir.join(path)),
+            Folder::Filtered { path, include, exclude } => Folder::Filtered {
+                path: dir.join(path), include, exclude
+            }
+        }).collect());
+        self.template_path = self.template_path.map(|template_path| match template_path {
+            TemplatePath::Single(path) => TemplatePath::Single(dir.join(path)),
+            TemplatePath::Multiple(paths) => TemplatePath::Multiple(
+                paths.into_iter().map(|path| dir.join(path)).collect())
+        });
+        self.atlas = self.atlas.into_iter().map(|entry| entry.relative_to(dir)).collect();
+        self
+    }
+
+    /// Replaces the environment variables in every path of the layer.
+    fn expand_env(mut self) -> Result<ConfigLayer, PackerError> {
+        self.extends = self.extends.map(|path| expand_env(&path)).transpose()?;
+        self.output_path = self.output_path.map(|path| expand_env(&path)).transpose()?;
+        self.folders = self.folders.map(|folders| folders.into_iter().map(|folder| match folder {
+            Folder::Path(path) => Ok(Folder::Path(expand_env(&path)?)),
+            Folder::Filtered { path, include, exclude } => Ok(Folder::Filtered {
+                path: expand_env(&path)?, include, exclude
+            })
+        }).collect::<Result<_, PackerError>>()).transpose()?;
+        self.template_path = self.template_path.map(|template_path| match template_path {
+            TemplatePath::Single(path) => Ok(TemplatePath::Single(expand_env(&path)?)),
+            TemplatePath::Multiple(paths) => Ok(TemplatePath::Multiple(
+                paths.iter().map(|path| expand_env(path)).collect::<Result<_, PackerError>>()?))
+        }).transpose()?;
+        self.atlas = self.atlas
+            .into_iter()
+            .map(ConfigLayer::expand_env)
+            .collect::<Result<_, PackerError>>()?;
+        Ok(self)
+    }
+
+    /// Every setting of `self`, and the settings of `base` for the rest.
+    fn or(self, base: ConfigLayer) -> ConfigLayer {
+        let mut extended_from = base.extended_from;
+        extended_from.extend(self.extended_from);
+        ConfigLayer {
+            extends: self.extends.or(base.extends),
+            extended_from,
+            name: self.name.or(base.name),
+            output_path: self.output_path.or(base.output_path),
+            folders: self.folders.or(base.folders),
+            extensions: self.extensions.or(base.extensions),
+            allow_normal_output: self.allow_normal_output.or(base.allow_normal_output),
+            template_path: self.template_path.or(base.template_path),
+            strict: self.strict.or(base.strict),
+            output_type: self.output_type.or(base.output_type),
+            image_options: merge(self.image_options, base.image_options, ImageOptionsLayer::or),
+            binary_options: merge(self.binary_options, base.binary_options, BinaryOptionsLayer::or),
+            features: merge(self.features, base.features, FeaturesLayer::or),
+            atlas: if self.atlas.is_empty() { base.atlas } else { self.atlas }
+        }
+    }
+
+    /// The configs of every atlas in the file.
+    pub(super) fn into_configs(mut self) -> anyhow::Result<Vec<Config>> {
+        if self.atlas.is_empty() {
+            return Ok(vec![self.into_config()?]);
+        }
+
+        let entries = std::mem::take(&mut self.atlas);
+        let mut configs: Vec<Config> = vec![];
+        for (index, entry) in entries.into_iter().enumerate() {
+            if !entry.atlas.is_empty() {
+                Err(PackerError::InvalidConfig(format!("atlas {index} cannot contain more atlases")))?
+            }
+            let config = entry.or(self.clone()).into_config().map_err(|err| match err {
+                PackerError::InvalidConfig(reason) => PackerError::InvalidConfig(format!("atlas {index}: {reason}")),
+                err => err
+            })?;
+            let same_output = configs.iter().find(|other| {
+                other.name == config.name && other.output_path == config.output_path
+            });
+            if same_output.is_some() {
+                Err(PackerError::InvalidConfig(format!(
+                    "atlas {index}: another atlas is already named `{}` in `{}`",
+                    config.name, config.output_path.display()
+                )))?
+            }
+            configs.push(config);
+        }
+        Ok(configs)
+    }
+
+    fn into_config(self) -> Result<Config, PackerError> {
+        let missing = |field: &str| PackerError::InvalidConfig(format!("missing field `{field}`"));
+        Ok(Config {
+            name: self.name.ok_or_else(|| missing("name"))?,
+            output_path: self.output_path.ok_or_else(|| missing("output_path"))?,
+            folders: self.folders.ok_or_else(|| missing("folders"))?,
+            extensions: self.extensions.unwrap_or_else(default_extensions),
+            allow_normal_output: self.allow_normal_output.unwrap_or(default_allow_normal_output()),
+            template_path: self.template_path,
+            strict: self.strict.unwrap_or_default(),
+            output_type: self.output_type.unwrap_or_default(),
+            image_options: self.image_options.unwrap_or_default().into_options(),
+            binary_options: self.binary_options.unwrap_or_default().into_options(),
+            features: self.features.unwrap_or_default().into_features(),
+            extended_from: self.extended_from
+        })
+    }
+}
+
+impl ImageOptionsLayer {
+    fn or(self, base: ImageOptionsLayer) -> ImageOptionsLayer {
+        ImageOptionsLayer {
+            output_extension: self.output_extension.or(base.output_extension),
+            max_size: self.max_size.or(base.max_size),
+            show_extension: self.show_extension.or(base.show_extension),
+            size_mode: self.size_mode.or(base.size_mode),
+            trim: self.trim.or(base.trim),
+            allow_rotation: self.allow_rotation.or(base.allow_rotation),
+            padding: self.padding.or(base.padding),
+            border: self.border.or(base.border),
+            extrude: self.extrude.or(base.extrude),
+            remove_duplicates: self.remove_duplicates.or(base.remove_duplicates),
+            show_aliases: self.show_aliases.or(base.show_aliases),
+            frame_order: self.frame_order.or(base.frame_order),
+            packer: self.packer.or(base.packer),
+            heuristic: self.heuristic.or(base.heuristic),
+            sort_by: self.sort_by.or(base.sort_by)
+        }
+    }
+
+    fn into_options(self) -> ImageOptions {
+        let default = ImageOptions::default();
+        ImageOptions {
+            output_extension: self.output_extension.unwrap_or(default.output_extension),
+            max_size: self.max_size.unwrap_or(default.max_size),
+            show_extension: self.show_extension.unwrap_or(default.show_extension),
+            size_mode: self.size_mode.unwrap_or(default.size_mode),
+            trim: self.trim.unwrap_or(default.trim),
+            allow_rotation: self.allow_rotation.unwrap_or(default.allow_rotation),
+            padding: self.padding.unwrap_or(default.padding),
+            border: self.border.unwrap_or(default.border),
+            extrude: self.extrude.unwrap_or(default.extrude),
+            remove_duplicates: self.remove_duplicates.unwrap_or(default.remove_duplicates),
+            show_aliases: self.show_aliases.unwrap_or(default.show_aliases),
+            frame_order: self.frame_order.unwrap_or(default.frame_order),
+            packer: self.packer.unwrap_or(default.packer),
+            heuristic: self.heuristic.unwrap_or(default.heuristic),
+            sort_by: self.sort_by.unwrap_or(default.sort_by)
+        }
+    }
+}
+
+impl BinaryOptionsLayer {
+    fn or(self, base: BinaryOptionsLayer) -> BinaryOptionsLayer {
+        BinaryOptionsLayer {
+            endianness: self.endianness.or(base.endianness),
+            coordinate_width: self.coordinate_width.or(base.coordinate_width),
+            string_encoding: self.string_encoding.or(base.string_encoding)
+        }
+    }
+
+    fn into_options(self) -> BinaryOptions {
+        BinaryOptions {
+            endianness: self.endianness.unwrap_or_default(),
+            coordinate_width: self.coordinate_width.unwrap_or_default(),
+            string_encoding: self.string_encoding.unwrap_or_default()
+        }
+    }
+}
+
+impl FeaturesLayer {
+    fn or(self, base: FeaturesLayer) -> FeaturesLayer {
+        FeaturesLayer {
+            nine_patch: self.nine_patch.or(base.nine_patch),
+            aseprite: self.aseprite.or(base.aseprite),
+            ase_sheet: self.ase_sheet.or(base.ase_sheet),
+            ase_split_layers: self.ase_split_layers.or(base.ase_split_layers),
+            ase_include_layers: self.ase_include_layers.or(base.ase_include_layers),
+            ase_exclude_layers: self.ase_exclude_layers.or(base.ase_exclude_layers),
+            ase_ignore_hidden: self.ase_ignore_hidden.or(base.ase_ignore_hidden)
+        }
+    }
+
+    fn into_features(self) -> Features {
+        let default = Features::default();
+        Features {
+            nine_patch: self.nine_patch.unwrap_or(default.nine_patch),
+            aseprite: self.aseprite.unwrap_or(default.aseprite),
+            ase_sheet: self.ase_sheet.unwrap_or(default.ase_sheet),
+            ase_split_layers: self.ase_split_layers.unwrap_or(default.ase_split_layers),
+            ase_include_layers: self.ase_include_layers.unwrap_or(default.ase_include_layers),
+            ase_exclude_layers: self.ase_exclude_layers.unwrap_or(default.ase_exclude_layers),
+            ase_ignore_hidden: self.ase_ignore_hidden.unwrap_or(default.ase_ignore_hidden)
+        }
+    }
+}
+
+/// Replaces every `${NAME}` in the path with the environment variable `NAME`.
+fn expand_env(path: &Path) -> Result<PathBuf, PackerError> {
+    let Some(text) = path.to_str() else {
+        return Ok(path.to_owned());
+    };
+
+    let mut expanded = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("${") {
+        let Some(length) = rest[start..].find('}') else {
+            break;
+        };
+        let name = &rest[start + 2..start + length];
+        let value = std::env::var(name).map_err(|_| PackerError::InvalidConfig(
+            format!("the environment variable `{name}` used in `{text}` is not set")))?;
+        expanded.push_str(&rest[..start]);
+        expanded.push_str(&value);
+        rest = &rest[start + length + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(PathBuf::from(expanded))
+}
+
+/// Reads a config file as `T` in the format of the extension. RON files may
+/// leave out the `Some` around optional settings, like in a plain `Config`.
+///
+/// The errors point at the line and the column where reading failed.
+fn read_config_file<T: DeserializeOwned>(path: &Path, extension: &str) -> anyhow::Result<T> {
+    let buffer = std::fs::read_to_string(path)?;
+    let syntax_error = |line, column, message: String| PackerError::ConfigSyntax {
+        file: path.to_owned(), line, column, message
+    };
+
+    match extension {
+        "ron" => {
+            let options = ron::Options::default()
+                .with_default_extension(ron::extensions::Extensions::IMPLICIT_SOME);
+            let mut deserializer = ron::Deserializer::from_str_with_options(&buffer, options)
+                .map_err(|err| syntax_error(err.position.line, err.position.col, err.code.to_string()))?;
+            let value = T::deserialize(&mut deserializer)
+                .and_then(|value| deserializer.end().map(|_| value))
+                .map_err(|err| {
+                    let err = deserializer.span_error(err);
+                    syntax_error(err.position.line, err.position.col, err.code.to_string())
+                })?;
+            Ok(value)
+        }
+        "json" => {
+            let mut deserializer = serde_json::Deserializer::from_str(&buffer);
+            let value = T::deserialize(&mut deserializer)
+                .and_then(|value| deserializer.end().map(|_| value))
+                .map_err(|err| {
+                    let location = format!(" at line {} column {}", err.line(), err.column());
+                    let message = err.to_string().trim_end_matches(&location).to_owned();
+                    syntax_error(err.line(), err.column(), message)
+                })?;
+            Ok(value)
+        }
+        "toml" => {
+            let deserializer = toml::Deserializer::new(&buffer);
+            let value = T::deserialize(deserializer).map_err(|err| {
+                let offset = err.span().map_or(0, |span| span.start);
+                let (line, column) = line_and_column(&buffer, offset);
+                syntax_error(line, column, err.message().trim_end().to_owned())
+            })?;
+            Ok(value)
+        }
+        _ => Err(PackerError::UnsupportedFormat)?
+    }
+}
+
+/// The line and the column, starting at 1, of the byte at `offset`.
+fn line_and_column(text: &str, offset: usize) -> (usize, usize) {
+    let before = &text[..offset.min(text.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.rsplit('\n').next().unwrap_or_default().chars().count() + 1;
+    (line, column)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A folder of config files that is removed at the end of the test.
+    struct Files(PathBuf);
+
+    impl Files {
+        fn new(test: &str) -> Files {
+            let dir = std::env::temp_dir().join(format!("cluttered-config-{test}-{}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            Files(dir)
+        }
+
+        fn write(&self, name: &str, content: &str) -> PathBuf {
+            let path = self.0.join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, content).unwrap();
+            path
+        }
+    }
+
+    impl Drop for Files {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn configs(path: &Path) -> anyhow::Result<Vec<Config>> {
+        ConfigLayer::from_path(path, &mut ConfigReader::default())?.into_configs()
+    }
+
+    /// The reason the config file is invalid.
+    fn invalid_config(path: &Path) -> String {
+        let Err(err) = configs(path) else { panic!("{} is valid", path.display()) };
+        match err.downcast::<PackerError>() {
+            Ok(PackerError::InvalidConfig(reason)) => reason,
+            other => panic!("not an invalid config: {other:?}")
+        }
+    }
+
+    #[test]
+    fn child_overrides_a_single_nested_key() {
+        let files = Files::new("override");
+        files.write("base.toml", r#"
+            name = "base"
+            output_path = "out"
+            folders = ["sprites"]
+            [image_options]
+            max_size = 256
+            trim = true
+            padding = 2
+        "#);
+        let child = files.write("child.toml", r#"
+            extends = "base.toml"
+            name = "child"
+            [image_options]
+            padding = 4
+        "#);
+
+        let config = configs(&child).unwrap().remove(0);
+        assert_eq!(config.name, "child");
+        assert_eq!(config.image_options.padding, 4);
+        assert_eq!(config.image_options.max_size, 256);
+        assert!(config.image_options.trim);
+        assert_eq!(config.extended_from, vec![files.0.join("base.toml")]);
+    }
+
+    #[test]
+    fn grandparent_paths_are_relative_to_its_own_file() {
+        let files = Files::new("relative");
+        files.write("shared/grandparent.ron", r#"(
+            output_path: "out",
+            folders: ["sprites", (path: "ui", include: ["*.png"])],
+            template_path: "template.txt",
+        )"#);
+        files.write("levels/parent.json", r#"{ "extends": "../shared/grandparent.ron", "name": "parent" }"#);
+        let child = files.write("child.toml", r#"extends = "levels/parent.json""#);
+
+        let config = configs(&child).unwrap().remove(0);
+        let input_path = Some(child);
+        let shared = files.0.join("levels/../shared");
+        assert_eq!(config.name, "parent");
+        assert_eq!(config.folder_paths(&input_path), vec![shared.join("sprites"), shared.join("ui")]);
+        assert_eq!(config.fixed_output_path(&input_path), shared.join("out"));
+        assert_eq!(config.template_paths(&input_path), vec![shared.join("template.txt")]);
+    }
+
+    #[test]
+    fn extends_cycle_is_an_error() {
+        let files = Files::new("cycle");
+        files.write("a.toml", "extends = \"b.toml\"\nname = \"a\"");
+        let b = files.write("b.toml", "extends = \"a.toml\"\nname = \"b\"");
+
+        let reason = invalid_config(&b);
+        assert!(reason.ends_with("ends up extending itself"), "{reason}");
+    }
+
+    #[test]
+    fn unset_environment_variable_is_an_error() {
+        let files = Files::new("env");
+        let path = files.write("config.toml", r#"
+            name = "env"
+            output_path = "out"
+            folders = ["${CLUTTERED_TEST_UNSET_VARIABLE}/sprites"]
+        "#);
+
+        let reason = invalid_config(&path);
+        assert!(reason.contains("`CLUTTERED_TEST_UNSET_VARIABLE`"), "{reason}");
+    }
+}
@@ -1,10 +1,15 @@
-use std::path::{PathBuf, Path};
+use std::{collections::HashSet, path::{Component, PathBuf, Path}};
 use ron::ser::{PrettyConfig, to_string_pretty};
 use serde_json as json;
 
 use binary_rw::{MemoryStream, BinaryWriter};
 
-use super::{PackerAtlas, Config, TemplateGlobals, TemplatePath};
+use super::{
+    PackerAtlas, Config, TemplateGlobals, TemplatePath, TextureData,
+    Animation, AnimationFrame, AnimationDirection, Rect, Point,
+    BinaryOptions, Endianness, CoordinateWidth, StringEncoding
+};
+use crate::error::PackerError;
 
 pub(super) trait Output {
     fn out(&self, path: PathBuf, atlas: PackerAtlas) -> anyhow::Result<()>;
@@ -16,6 +21,8 @@ pub(super) struct JsonOutput;
 pub(super) struct RonOutput;
 #[derive(Default)]
 pub(super) struct TomlOutput;
+#[derive(Default)]
+pub(super) struct GodotOutput;
 
 pub(super) struct BinaryOutput<'a>(pub(super) &'a Config);
 
@@ -119,31 +126,250 @@ impl Output for TomlOutput {
     }
 }
 
+impl GodotOutput {
+    /// Writes the Rect2 region and the margin of the trimmed area of a frame.
+    /// AtlasTexture cannot rotate its region, so the margin of rotated frames
+    /// is measured with the size of the unrotated image.
+    fn atlas_texture(data: &TextureData) -> String {
+        let mut resource = format!(
+            "region = Rect2({}, {}, {}, {})\n", data.x, data.y, data.width, data.height);
+        let (width, height) = if data.rotated {
+            (data.height, data.width)
+        } else {
+            (data.width, data.height)
+        };
+        if width != data.source_width || height != data.source_height {
+            resource.push_str(&format!(
+                "margin = Rect2({}, {}, {}, {})\n",
+                data.offset_x, data.offset_y,
+                data.source_width - width,
+                data.source_height - height
+            ));
+        }
+        resource
+    }
+
+    fn page_name(atlas: &PackerAtlas, page: usize) -> String {
+        atlas.pages[page]
+            .file_name()
+            .and_then(std::ffi::OsStr::to_str)
+            .unwrap_or_default()
+            .to_owned()
+    }
+
+    /// The resource of a frame inside the atlas folder. The parts of the name that would
+    /// leave the folder, like the `..` of a source folder outside the config, are left out.
+    fn resource_path(frame_key: &str) -> PathBuf {
+        let frame_key = frame_key.replace('\\', "/");
+        let mut path: PathBuf = Path::new(&frame_key)
+            .components()
+            .filter(|component| matches!(component, Component::Normal(_)))
+            .collect();
+        path.as_mut_os_string().push(".tres");
+        path
+    }
+
+    /// Writes an AtlasTexture resource for every frame inside a folder named after the atlas.
+    fn write_atlas_textures(&self, path: &Path, atlas: &PackerAtlas) -> anyhow::Result<()> {
+        let mut written = HashSet::new();
+        for (frame_key, data) in atlas.frames.iter() {
+            let relative = Self::resource_path(frame_key);
+            if !written.insert(relative.clone()) {
+                return Err(PackerError::DuplicateGodotResource(relative).into());
+            }
+            let resource_path = path.join(&relative);
+            if let Some(parent) = resource_path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+
+            // Relative to the resource, the sheet lives in the parent of the atlas folder.
+            let depth = relative.components().count();
+            let sheet_path = format!("{}{}", "../".repeat(depth), Self::page_name(atlas, data.page));
+
+            let resource = format!(
+                "[gd_resource type=\"AtlasTexture\" load_steps=2 format=3]\n\n\
+                [ext_resource type=\"Texture2D\" path=\"{}\" id=\"1\"]\n\n\
+                [resource]\n\
+                atlas = ExtResource(\"1\")\n\
+                {}",
+                sheet_path,
+                Self::atlas_texture(data)
+            );
+            std::fs::write(resource_path, resource)?;
+        }
+        Ok(())
+    }
+
+    /// Godot only plays the animations forward, so the frames are laid out in the playing order.
+    fn playing_order(animation: &Animation) -> Vec<&AnimationFrame> {
+        let frames = animation.frames.iter();
+        match animation.direction {
+            AnimationDirection::Forward => frames.collect(),
+            AnimationDirection::Reverse => frames.rev().collect(),
+            AnimationDirection::PingPong => {
+                let len = animation.frames.len();
+                let back = animation.frames.iter().rev().skip(1).take(len.saturating_sub(2));
+                frames.chain(back).collect()
+            }
+        }
+    }
+
+    /// Writes a SpriteFrames resource with an animation for every Aseprite tag or file.
+    fn write_sprite_frames(&self, path: &Path, atlas: &PackerAtlas) -> anyhow::Result<()> {
+        let mut ext_resources = String::new();
+        for page in 0..atlas.pages.len() {
+            ext_resources.push_str(&format!(
+                "[ext_resource type=\"Texture2D\" path=\"{}\" id=\"{}\"]\n",
+                Self::page_name(atlas, page), page + 1
+            ));
+        }
+
+        let mut sub_resources = String::new();
+        let mut animations = vec![];
+        let mut sub_resource_count = 0;
+        for animation in atlas.animations.iter() {
+            let mut frames = vec![];
+            for frame in Self::playing_order(animation) {
+                let Some(data) = atlas.frames.get(&frame.name) else { continue };
+                sub_resources.push_str(&format!(
+                    "[sub_resource type=\"AtlasTexture\" id=\"AtlasTexture_{}\"]\n\
+                    atlas = ExtResource(\"{}\")\n\
+                    {}\n",
+                    sub_resource_count, data.page + 1, Self::atlas_texture(data)
+                ));
+                // With a speed of 1 FPS, the duration of a frame is in seconds.
+                frames.push(format!(
+                    "{{\n\"duration\": {:?},\n\"texture\": SubResource(\"AtlasTexture_{}\")\n}}",
+                    frame.duration as f32 / 1000.0,
+                    sub_resource_count
+                ));
+                sub_resource_count += 1;
+            }
+            animations.push(format!(
+                "{{\n\"frames\": [{}],\n\"loop\": true,\n\"name\": &\"{}\",\n\"speed\": 1.0\n}}",
+                frames.join(", "),
+                animation.name.replace('\\', "/")
+            ));
+        }
+
+        let resource = format!(
+            "[gd_resource type=\"SpriteFrames\" load_steps={} format=3]\n\n\
+            {}\n\
+            {}\
+            [resource]\n\
+            animations = [{}]\n",
+            atlas.pages.len() + sub_resource_count + 1,
+            ext_resources,
+            sub_resources,
+            animations.join(", ")
+        );
+        std::fs::write(path.with_extension("tres"), resource)?;
+        Ok(())
+    }
+}
+
+impl Output for GodotOutput {
+    fn out(&self, path: PathBuf, atlas: PackerAtlas) -> anyhow::Result<()> {
+        self.write_atlas_textures(&path, &atlas)?;
+        if !atlas.animations.is_empty() {
+            self.write_sprite_frames(&path, &atlas)?;
+        }
+        Ok(())
+    }
+}
+
+/// The first bytes of every binary atlas.
+pub(super) const BINARY_MAGIC: &[u8; 4] = b"CLTR";
+/// Bumped whenever a reader of the previous version can no longer read the file.
+pub(super) const BINARY_VERSION: u16 = 2;
+
+/// The frames were trimmed, so their offsets and source sizes may differ from their size.
+const FLAG_TRIMMED: u32 = 1;
+/// The frames may be rotated.
+const FLAG_ROTATED: u32 = 1 << 1;
+/// The atlas spans more than one page.
+const FLAG_MULTI_PAGE: u32 = 1 << 2;
+/// Everything after the header is big-endian.
+pub(super) const FLAG_BIG_ENDIAN: u32 = 1 << 3;
+/// The coordinates and sizes are 16-bit integers.
+pub(super) const FLAG_16BIT_COORDINATES: u32 = 1 << 4;
+/// The strings end with a zero byte instead of being prefixed with their length.
+pub(super) const FLAG_NULL_TERMINATED_STRINGS: u32 = 1 << 5;
+/// Every flag of the current version. Readers refuse the others, as they may
+/// change the layout.
+pub(super) const KNOWN_FLAGS: u32 = FLAG_TRIMMED
+    | FLAG_ROTATED
+    | FLAG_MULTI_PAGE
+    | FLAG_BIG_ENDIAN
+    | FLAG_16BIT_COORDINATES
+    | FLAG_NULL_TERMINATED_STRINGS;
+
+impl<'a> BinaryOutput<'a> {
+    fn flags(&self, atlas: &PackerAtlas) -> u32 {
+        let options = &self.0.binary_options;
+        let mut flags = 0;
+        if self.0.image_options.trim {
+            flags |= FLAG_TRIMMED;
+        }
+        if self.0.image_options.allow_rotation {
+            flags |= FLAG_ROTATED;
+        }
+        if atlas.pages.len() > 1 {
+            flags |= FLAG_MULTI_PAGE;
+        }
+        if let Endianness::Big = options.endianness {
+            flags |= FLAG_BIG_ENDIAN;
+        }
+        if let CoordinateWidth::U16 = options.coordinate_width {
+            flags |= FLAG_16BIT_COORDINATES;
+        }
+        if let StringEncoding::NullTerminated = options.string_encoding {
+            flags |= FLAG_NULL_TERMINATED_STRINGS;
+        }
+        flags
+    }
+}
+
 impl<'a> Output for BinaryOutput<'a> {
     fn out(&self, path: PathBuf, atlas: PackerAtlas) -> anyhow::Result<()> {
         let path = path.with_extension("bin");
         let mut fs = MemoryStream::new();
-        let mut writer = binary_rw::BinaryWriter::new(&mut fs, binary_rw::Endian::Little);
+        // The header is always little-endian, so the flags can be read
+        // before knowing the layout of the rest.
+        let mut header = BinaryWriter::new(&mut fs, binary_rw::Endian::Little);
+        header.write_bytes(BINARY_MAGIC)?;
+        header.write_u16(BINARY_VERSION)?;
+        header.write_u32(self.flags(&atlas))?;
+
+        let options = &self.0.binary_options;
+        let mut writer = AtlasWriter::new(&mut fs, options);
         let sheet_path = atlas.sheet_path.to_str().unwrap_or_default().replace('\\', "/");
-        writer.write_sharp_string(sheet_path)?;
+        writer.write_string(sheet_path)?;
+        writer.write_u32(atlas.pages.len() as u32)?;
+        for page in atlas.pages {
+            let page = page.to_str().unwrap_or_default().replace('\\', "/");
+            writer.write_string(page)?;
+        }
         let length = atlas.frames.len();
         writer.write_u32(length as u32)?;
         for (frame_key, data) in atlas.frames {
-            let frame_key = frame_key.replace('\\', "/");
-            writer.write_sharp_string(frame_key)?;
-            writer.write_u32(data.x)?;
-            writer.write_u32(data.y)?;
-            writer.write_u32(data.width)?;
-            writer.write_u32(data.height)?;
-            if !self.0.features.nine_patch {
-                continue;
-            }
-            writer.write_bool(data.nine_patch.is_some())?;
-            if let Some(nine_patch) = data.nine_patch {
-                writer.write_u32(nine_patch.x)?;
-                writer.write_u32(nine_patch.y)?;
-                writer.write_u32(nine_patch.w)?;
-                writer.write_u32(nine_patch.h)?;
+            // Every frame is prefixed with its size, so that readers can skip
+            // the fields added after the ones they know.
+            let mut record = MemoryStream::new();
+            write_frame(&mut AtlasWriter::new(&mut record, options), frame_key, data)?;
+            let record: Vec<u8> = record.into();
+            writer.write_u32(record.len() as u32)?;
+            writer.write_bytes(record)?;
+        }
+
+        writer.write_u32(atlas.animations.len() as u32)?;
+        for animation in atlas.animations {
+            writer.write_string(animation.name.replace('\\', "/"))?;
+            writer.write_u8(animation.direction as u8)?;
+            writer.write_u32(animation.frames.len() as u32)?;
+            for frame in animation.frames {
+                writer.write_string(frame.name.replace('\\', "/"))?;
+                writer.write_u32(frame.duration)?;
             }
         }
 
@@ -153,6 +379,126 @@ impl<'a> Output for BinaryOutput<'a> {
     }
 }
 
+fn write_frame(writer: &mut AtlasWriter, frame_key: String, data: TextureData) -> anyhow::Result<()> {
+    writer.write_string(frame_key.replace('\\', "/"))?;
+    writer.write_coordinate(data.x)?;
+    writer.write_coordinate(data.y)?;
+    writer.write_coordinate(data.width)?;
+    writer.write_coordinate(data.height)?;
+    writer.write_u32(data.page as u32)?;
+    writer.write_coordinate(data.source_width)?;
+    writer.write_coordinate(data.source_height)?;
+    writer.write_coordinate(data.offset_x)?;
+    writer.write_coordinate(data.offset_y)?;
+    writer.write_bool(data.rotated)?;
+    writer.write_string(data.alias_of.unwrap_or_default())?;
+    write_nine_patch(writer, data.nine_patch)?;
+    write_pivot(writer, data.pivot)?;
+    writer.write_u32(data.slices.len() as u32)?;
+    for slice in data.slices {
+        writer.write_string(slice.name)?;
+        writer.write_signed_coordinate(slice.x)?;
+        writer.write_signed_coordinate(slice.y)?;
+        writer.write_coordinate(slice.w)?;
+        writer.write_coordinate(slice.h)?;
+        write_nine_patch(writer, slice.nine_patch)?;
+        write_pivot(writer, slice.pivot)?;
+    }
+    Ok(())
+}
+
+fn write_nine_patch(writer: &mut AtlasWriter, nine_patch: Option<Rect>) -> anyhow::Result<()> {
+    writer.write_bool(nine_patch.is_some())?;
+    if let Some(nine_patch) = nine_patch {
+        writer.write_coordinate(nine_patch.x)?;
+        writer.write_coordinate(nine_patch.y)?;
+        writer.write_coordinate(nine_patch.w)?;
+        writer.write_coordinate(nine_patch.h)?;
+    }
+    Ok(())
+}
+
+fn write_pivot(writer: &mut AtlasWriter, pivot: Option<Point>) -> anyhow::Result<()> {
+    writer.write_bool(pivot.is_some())?;
+    if let Some(pivot) = pivot {
+        writer.write_signed_coordinate(pivot.x)?;
+        writer.write_signed_coordinate(pivot.y)?;
+    }
+    Ok(())
+}
+
+/// A `BinaryWriter` writing the coordinates and strings as set in the `binary_options`.
+struct AtlasWriter<'a, 'b> {
+    writer: BinaryWriter<'a>,
+    options: &'b BinaryOptions
+}
+
+impl<'a, 'b> AtlasWriter<'a, 'b> {
+    fn new(stream: &'a mut MemoryStream, options: &'b BinaryOptions) -> Self {
+        let endian = match options.endianness {
+            Endianness::Little => binary_rw::Endian::Little,
+            Endianness::Big => binary_rw::Endian::Big
+        };
+        AtlasWriter { writer: BinaryWriter::new(stream, endian), options }
+    }
+
+    fn write_string<S>(&mut self, value: S) -> anyhow::Result<()>
+    where S: AsRef<str> {
+        match self.options.string_encoding {
+            StringEncoding::LengthPrefixed => self.writer.write_sharp_string(value)?,
+            StringEncoding::NullTerminated => {
+                self.writer.write_bytes(value.as_ref().as_bytes())?;
+                self.writer.write_u8(0)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_coordinate(&mut self, value: u32) -> anyhow::Result<()> {
+        match self.options.coordinate_width {
+            CoordinateWidth::U32 => self.writer.write_u32(value)?,
+            CoordinateWidth::U16 => {
+                let value = u16::try_from(value)
+                    .map_err(|_| PackerError::CoordinateOutOfRange(value as i64))?;
+                self.writer.write_u16(value)?
+            }
+        };
+        Ok(())
+    }
+
+    fn write_signed_coordinate(&mut self, value: i32) -> anyhow::Result<()> {
+        match self.options.coordinate_width {
+            CoordinateWidth::U32 => self.writer.write_i32(value)?,
+            CoordinateWidth::U16 => {
+                let value = i16::try_from(value)
+                    .map_err(|_| PackerError::CoordinateOutOfRange(value as i64))?;
+                self.writer.write_i16(value)?
+            }
+        };
+        Ok(())
+    }
+
+    fn write_u32(&mut self, value: u32) -> anyhow::Result<()> {
+        self.writer.write_u32(value)?;
+        Ok(())
+    }
+
+    fn write_u8(&mut self, value: u8) -> anyhow::Result<()> {
+        self.writer.write_u8(value)?;
+        Ok(())
+    }
+
+    fn write_bool(&mut self, value: bool) -> anyhow::Result<()> {
+        self.writer.write_bool(value)?;
+        Ok(())
+    }
+
+    fn write_bytes(&mut self, value: Vec<u8>) -> anyhow::Result<()> {
+        self.writer.write_bytes(value)?;
+        Ok(())
+    }
+}
+
 pub(super) fn save_output<T>(path: PathBuf, atlas: PackerAtlas) -> anyhow::Result<()>
 where T: Default + Output {
     let output = T::default();
@@ -169,17 +515,28 @@ where T: Output {
 }
 
 
+/// Writes the values the same way as the `BinaryWriter` of .NET.
 pub(super) trait Sharp {
     fn write_sharp_string<S>(&mut self, value: S) -> anyhow::Result<()>
     where S: AsRef<str>;
+    fn write_7bit_encoded_int(&mut self, value: u32) -> anyhow::Result<()>;
 }
 
 impl<'a> Sharp for BinaryWriter<'a> {
     fn write_sharp_string<S>(&mut self, value: S) -> anyhow::Result<()>
     where S: AsRef<str> {
         let bytes = value.as_ref().as_bytes();
-        self.write_u8(bytes.len() as u8)?;
+        self.write_7bit_encoded_int(bytes.len() as u32)?;
         self.write_bytes(bytes)?;
         Ok(())
     }
+
+    fn write_7bit_encoded_int(&mut self, mut value: u32) -> anyhow::Result<()> {
+        while value >= 0x80 {
+            self.write_u8((value as u8) | 0x80)?;
+            value >>= 7;
+        }
+        self.write_u8(value as u8)?;
+        Ok(())
+    }
 }
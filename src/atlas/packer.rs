@@ -0,0 +1,457 @@
+use std::fmt;
+
+use crunch::{Item, Rect, Rotation};
+
+use super::{MaxRectsHeuristic, PackingAlgorithm};
+
+/// The items placed into an area, with their position and their data.
+/// Rotated items have their width and height swapped.
+pub(super) type Placed = Vec<(Rect, usize)>;
+
+/// A single packing algorithm, with `Best` resolved into every candidate.
+#[derive(Clone, Copy)]
+pub(super) enum Algorithm {
+    Crunch,
+    MaxRects(MaxRectsHeuristic),
+    Skyline,
+    Guillotine,
+    Shelf
+}
+
+impl Algorithm {
+    /// The algorithms to try for the configured `packer`.
+    pub(super) fn candidates(packer: PackingAlgorithm, heuristic: MaxRectsHeuristic) -> Vec<Algorithm> {
+        match packer {
+            PackingAlgorithm::Crunch => vec![Algorithm::Crunch],
+            PackingAlgorithm::MaxRects => vec![Algorithm::MaxRects(heuristic)],
+            PackingAlgorithm::Skyline => vec![Algorithm::Skyline],
+            PackingAlgorithm::Guillotine => vec![Algorithm::Guillotine],
+            PackingAlgorithm::Shelf => vec![Algorithm::Shelf],
+            PackingAlgorithm::Best => vec![
+                Algorithm::Crunch,
+                Algorithm::MaxRects(MaxRectsHeuristic::BestShortSideFit),
+                Algorithm::MaxRects(MaxRectsHeuristic::BestAreaFit),
+                Algorithm::MaxRects(MaxRectsHeuristic::BottomLeft),
+                Algorithm::Skyline,
+                Algorithm::Guillotine,
+                Algorithm::Shelf
+            ]
+        }
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Algorithm::Crunch => write!(f, "Crunch"),
+            Algorithm::MaxRects(MaxRectsHeuristic::BestShortSideFit) => write!(f, "MaxRects (BestShortSideFit)"),
+            Algorithm::MaxRects(MaxRectsHeuristic::BestAreaFit) => write!(f, "MaxRects (BestAreaFit)"),
+            Algorithm::MaxRects(MaxRectsHeuristic::BottomLeft) => write!(f, "MaxRects (BottomLeft)"),
+            Algorithm::Skyline => write!(f, "Skyline"),
+            Algorithm::Guillotine => write!(f, "Guillotine"),
+            Algorithm::Shelf => write!(f, "Shelf")
+        }
+    }
+}
+
+/// Packs the same items into differently sized areas.
+pub(super) enum RectPacker {
+    Crunch(crunch::Packer<usize>),
+    Custom(Algorithm, Vec<Item<usize>>)
+}
+
+impl RectPacker {
+    pub(super) fn new(algorithm: Algorithm, items: Vec<Item<usize>>) -> Self {
+        match algorithm {
+            Algorithm::Crunch => RectPacker::Crunch(crunch::Packer::with_items(items)),
+            _ => RectPacker::Custom(algorithm, items)
+        }
+    }
+
+    /// The area that the items cover at least.
+    pub(super) fn min_area(&self) -> usize {
+        match self {
+            // crunch does not give its items back, but it only uses the
+            // minimum area to skip sizes.
+            RectPacker::Crunch(_) => 0,
+            RectPacker::Custom(_, items) => items.iter().map(|item| item.w * item.h).sum()
+        }
+    }
+
+    /// Packs the items into the area. Unlike `crunch`, the other algorithms
+    /// keep the order of the items and skip the ones that do not fit, so
+    /// the error holds every item that could be placed.
+    pub(super) fn pack(&mut self, area: Rect) -> Result<Placed, Placed> {
+        let (packed, all_placed) = match self {
+            RectPacker::Crunch(packer) => match packer.pack(area) {
+                Ok(packed) => (packed.into_iter().collect(), true),
+                Err(packed) => (packed.into_iter().collect(), false)
+            },
+            RectPacker::Custom(algorithm, items) => {
+                let mut placer: Box<dyn Placer> = match algorithm {
+                    Algorithm::MaxRects(heuristic) => Box::new(MaxRects::new(area, *heuristic)),
+                    Algorithm::Skyline => Box::new(Skyline::new(area)),
+                    Algorithm::Guillotine => Box::new(Guillotine::new(area)),
+                    _ => Box::new(Shelf::new(area))
+                };
+                let mut packed = vec![];
+                for item in items.iter() {
+                    if let Some(rect) = placer.place(item.w, item.h, item.rot == Rotation::Allowed) {
+                        packed.push((Rect::new(area.x + rect.x, area.y + rect.y, rect.w, rect.h), item.data));
+                    }
+                }
+                let all_placed = packed.len() == items.len();
+                (packed, all_placed)
+            }
+        };
+        if all_placed { Ok(packed) } else { Err(packed) }
+    }
+}
+
+/// Places rectangles one after another into an area starting at the origin.
+trait Placer {
+    fn place(&mut self, w: usize, h: usize, allow_rotation: bool) -> Option<Rect>;
+}
+
+/// The sizes to try for an item, the rotated one last.
+fn orientations(w: usize, h: usize, allow_rotation: bool) -> impl Iterator<Item = (usize, usize)> {
+    let rotated = (allow_rotation && w != h).then_some((h, w));
+    std::iter::once((w, h)).chain(rotated)
+}
+
+/// Keeps every maximal free rectangle, and places each item into the free
+/// rectangle scoring best according to the heuristic.
+struct MaxRects {
+    heuristic: MaxRectsHeuristic,
+    free: Vec<Rect>
+}
+
+impl MaxRects {
+    fn new(area: Rect, heuristic: MaxRectsHeuristic) -> Self {
+        MaxRects { heuristic, free: vec![Rect::of_size(area.w, area.h)] }
+    }
+
+    fn score(&self, free: &Rect, w: usize, h: usize) -> (usize, usize) {
+        let (leftover_w, leftover_h) = (free.w - w, free.h - h);
+        match self.heuristic {
+            MaxRectsHeuristic::BestShortSideFit => (leftover_w.min(leftover_h), leftover_w.max(leftover_h)),
+            MaxRectsHeuristic::BestAreaFit => (free.area() - w * h, leftover_w.min(leftover_h)),
+            MaxRectsHeuristic::BottomLeft => (free.y + h, free.x)
+        }
+    }
+
+    fn split(&mut self, used: Rect) {
+        let mut free = Vec::with_capacity(self.free.len() + 4);
+        for rect in self.free.drain(..) {
+            if !rect.overlaps(&used) {
+                free.push(rect);
+                continue;
+            }
+            if used.x > rect.x {
+                free.push(Rect::new(rect.x, rect.y, used.x - rect.x, rect.h));
+            }
+            if used.right() < rect.right() {
+                free.push(Rect::new(used.right(), rect.y, rect.right() - used.right(), rect.h));
+            }
+            if used.y > rect.y {
+                free.push(Rect::new(rect.x, rect.y, rect.w, used.y - rect.y));
+            }
+            if used.bottom() < rect.bottom() {
+                free.push(Rect::new(rect.x, used.bottom(), rect.w, rect.bottom() - used.bottom()));
+            }
+        }
+
+        // Drop the free rectangles that are inside of another one.
+        let mut i = 0;
+        while i < free.len() {
+            let contained = free.iter().enumerate().any(|(j, other)| {
+                i != j && other.contains(&free[i]) && (other != &free[i] || j < i)
+            });
+            if contained {
+                free.swap_remove(i);
+            } else {
+                i += 1;
+            }
+        }
+        self.free = free;
+    }
+}
+
+impl Placer for MaxRects {
+    fn place(&mut self, w: usize, h: usize, allow_rotation: bool) -> Option<Rect> {
+        let mut best: Option<((usize, usize), Rect)> = None;
+        for free in self.free.iter() {
+            for (w, h) in orientations(w, h, allow_rotation) {
+                if w > free.w || h > free.h {
+                    continue;
+                }
+                let score = self.score(free, w, h);
+                if best.is_none_or(|(best_score, _)| score < best_score) {
+                    best = Some((score, Rect::new(free.x, free.y, w, h)));
+                }
+            }
+        }
+        let (_, rect) = best?;
+        self.split(rect);
+        Some(rect)
+    }
+}
+
+/// Keeps the top edge of the placed items, and places each item as low as
+/// possible, then as far left as possible.
+struct Skyline {
+    width: usize,
+    height: usize,
+    /// The segments of the skyline as `(x, y, width)`, from left to right.
+    segments: Vec<(usize, usize, usize)>
+}
+
+impl Skyline {
+    fn new(area: Rect) -> Self {
+        Skyline { width: area.w, height: area.h, segments: vec![(0, 0, area.w)] }
+    }
+
+    /// The height at which an item of the width would rest when starting at the segment.
+    fn fit(&self, index: usize, w: usize, h: usize) -> Option<usize> {
+        let x = self.segments[index].0;
+        if x + w > self.width {
+            return None;
+        }
+        let mut y = 0;
+        let mut covered = 0;
+        for &(_, segment_y, segment_w) in self.segments[index..].iter() {
+            y = y.max(segment_y);
+            covered += segment_w;
+            if covered >= w {
+                break;
+            }
+        }
+        (y + h <= self.height).then_some(y)
+    }
+
+    fn add(&mut self, index: usize, rect: Rect) {
+        self.segments.insert(index, (rect.x, rect.bottom(), rect.w));
+        let i = index + 1;
+        while i < self.segments.len() {
+            let (x, y, w) = self.segments[i];
+            if x >= rect.right() {
+                break;
+            }
+            let shrink = rect.right() - x;
+            if shrink >= w {
+                self.segments.remove(i);
+            } else {
+                self.segments[i] = (x + shrink, y, w - shrink);
+                break;
+            }
+        }
+
+        // Merge the neighbouring segments at the same height.
+        let mut i = 0;
+        while i + 1 < self.segments.len() {
+            if self.segments[i].1 == self.segments[i + 1].1 {
+                self.segments[i].2 += self.segments[i + 1].2;
+                self.segments.remove(i + 1);
+            } else {
+                i += 1;
+            }
+        }
+    }
+}
+
+impl Placer for Skyline {
+    fn place(&mut self, w: usize, h: usize, allow_rotation: bool) -> Option<Rect> {
+        let mut best: Option<((usize, usize), usize, Rect)> = None;
+        for index in 0..self.segments.len() {
+            for (w, h) in orientations(w, h, allow_rotation) {
+                let Some(y) = self.fit(index, w, h) else { continue };
+                let x = self.segments[index].0;
+                let score = (y + h, x);
+                if best.is_none_or(|(best_score, _, _)| score < best_score) {
+                    best = Some((score, index, Rect::new(x, y, w, h)));
+                }
+            }
+        }
+        let (_, index, rect) = best?;
+        self.add(index, rect);
+        Some(rect)
+    }
+}
+
+/// Places each item into the free rectangle that leaves the least area, and
+/// splits the rest of it in two along the shorter leftover side.
+struct Guillotine {
+    free: Vec<Rect>
+}
+
+impl Guillotine {
+    fn new(area: Rect) -> Self {
+        Guillotine { free: vec![Rect::of_size(area.w, area.h)] }
+    }
+}
+
+impl Placer for Guillotine {
+    fn place(&mut self, w: usize, h: usize, allow_rotation: bool) -> Option<Rect> {
+        let mut best: Option<(usize, usize, Rect)> = None;
+        for (index, free) in self.free.iter().enumerate() {
+            for (w, h) in orientations(w, h, allow_rotation) {
+                if w > free.w || h > free.h {
+                    continue;
+                }
+                let leftover = free.area() - w * h;
+                if best.is_none_or(|(best_leftover, _, _)| leftover < best_leftover) {
+                    best = Some((leftover, index, Rect::new(free.x, free.y, w, h)));
+                }
+            }
+        }
+        let (_, index, rect) = best?;
+        let free = self.free.swap_remove(index);
+        let (right, bottom) = if free.w - rect.w < free.h - rect.h {
+            (
+                Rect::new(rect.right(), free.y, free.w - rect.w, rect.h),
+                Rect::new(free.x, rect.bottom(), free.w, free.h - rect.h)
+            )
+        } else {
+            (
+                Rect::new(rect.right(), free.y, free.w - rect.w, free.h),
+                Rect::new(free.x, rect.bottom(), rect.w, free.h - rect.h)
+            )
+        };
+        self.free.extend([right, bottom].into_iter().filter(|rect| rect.area() > 0));
+        Some(rect)
+    }
+}
+
+/// Places the items side by side on rows, starting a new row below the
+/// last one when an item does not fit on any of them.
+struct Shelf {
+    width: usize,
+    height: usize,
+    /// The rows as `(y, height, used width)`, from top to bottom.
+    shelves: Vec<(usize, usize, usize)>
+}
+
+impl Shelf {
+    fn new(area: Rect) -> Self {
+        Shelf { width: area.w, height: area.h, shelves: vec![] }
+    }
+}
+
+impl Placer for Shelf {
+    fn place(&mut self, w: usize, h: usize, allow_rotation: bool) -> Option<Rect> {
+        let last = self.shelves.len().checked_sub(1);
+        for (index, shelf) in self.shelves.iter_mut().enumerate() {
+            let (y, shelf_height, used) = *shelf;
+            // Prefer the orientation wasting the least height of the row.
+            let fit = orientations(w, h, allow_rotation)
+                .filter(|&(w, h)| {
+                    let fits_height = h <= shelf_height || (Some(index) == last && y + h <= self.height);
+                    used + w <= self.width && fits_height
+                })
+                .min_by_key(|&(_, h)| shelf_height.abs_diff(h));
+            if let Some((w, h)) = fit {
+                *shelf = (y, shelf_height.max(h), used + w);
+                return Some(Rect::new(used, y, w, h));
+            }
+        }
+
+        // Lay the item flat on a new row to keep the rows low.
+        let y = self.shelves.last().map_or(0, |&(y, h, _)| y + h);
+        let (w, h) = orientations(w, h, allow_rotation)
+            .filter(|&(w, h)| w <= self.width && y + h <= self.height)
+            .min_by_key(|&(_, h)| h)?;
+        self.shelves.push((y, h, w));
+        Some(Rect::new(0, y, w, h))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn all_algorithms() -> Vec<Algorithm> {
+        Algorithm::candidates(PackingAlgorithm::Best, MaxRectsHeuristic::default())
+    }
+
+    /// Items of various sizes, the same on every run.
+    fn items(count: usize, rotation: Rotation) -> Vec<Item<usize>> {
+        let mut seed = 7usize;
+        let mut next = |max: usize| {
+            seed = (seed * 1103515245 + 12345) % (1 << 31);
+            1 + seed % max
+        };
+        (0..count).map(|i| Item::new(i, next(24), next(12), rotation)).collect()
+    }
+
+    /// Checks that the placed items are inside the area, do not overlap, and
+    /// only have their size swapped when rotating them is allowed.
+    fn check_placed(placed: &Placed, items: &[Item<usize>], area: Rect) {
+        for (index, (rect, data)) in placed.iter().enumerate() {
+            assert!(area.contains(rect), "{rect:?} is outside of {area:?}");
+            let item = &items[*data];
+            let same = rect.w == item.w && rect.h == item.h;
+            let rotated = rect.w == item.h && rect.h == item.w;
+            assert!(same || (rotated && item.rot == Rotation::Allowed), "{rect:?} does not match {}", data);
+            for (other, _) in placed[index + 1..].iter() {
+                assert!(!rect.overlaps(other), "{rect:?} overlaps {other:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn placed_items_do_not_overlap() {
+        let area = Rect::new(3, 5, 128, 128);
+        for rotation in [Rotation::None, Rotation::Allowed] {
+            let items = items(60, rotation);
+            for algorithm in all_algorithms() {
+                let (Ok(placed) | Err(placed)) = RectPacker::new(algorithm, items.clone()).pack(area);
+                assert!(!placed.is_empty(), "{algorithm} placed nothing");
+                check_placed(&placed, &items, area);
+            }
+        }
+    }
+
+    #[test]
+    fn every_item_is_placed_into_a_large_area() {
+        let area = Rect::of_size(512, 512);
+        let items = items(40, Rotation::Allowed);
+        for algorithm in all_algorithms() {
+            let placed = RectPacker::new(algorithm, items.clone()).pack(area);
+            let Ok(placed) = placed else { panic!("{algorithm} failed") };
+            assert_eq!(placed.len(), items.len(), "{algorithm}");
+            check_placed(&placed, &items, area);
+        }
+    }
+
+    #[test]
+    fn items_are_rotated_only_when_allowed() {
+        let area = Rect::of_size(30, 10);
+        for algorithm in all_algorithms() {
+            let items = vec![Item::new(0, 10, 30, Rotation::Allowed)];
+            let Ok(placed) = RectPacker::new(algorithm, items.clone()).pack(area) else {
+                panic!("{algorithm} did not rotate the item")
+            };
+            assert_eq!((placed[0].0.w, placed[0].0.h), (30, 10), "{algorithm}");
+
+            let items = vec![Item::new(0, 10, 30, Rotation::None)];
+            assert!(RectPacker::new(algorithm, items).pack(area).is_err(), "{algorithm}");
+        }
+    }
+
+    #[test]
+    fn fails_when_an_item_is_larger_than_the_area() {
+        let area = Rect::of_size(32, 32);
+        for algorithm in all_algorithms() {
+            let items = vec![
+                Item::new(0, 8, 8, Rotation::Allowed),
+                Item::new(1, 33, 4, Rotation::Allowed),
+                Item::new(2, 8, 8, Rotation::Allowed)
+            ];
+            let Err(placed) = RectPacker::new(algorithm, items.clone()).pack(area) else {
+                panic!("{algorithm} placed an item larger than the area")
+            };
+            assert!(placed.iter().all(|(_, data)| *data != 1), "{algorithm}");
+            check_placed(&placed, &items, area);
+        }
+    }
+}
@@ -0,0 +1,596 @@
+use std::path::{Path, PathBuf, Component};
+
+use binary_rw::{BinaryReader, Endian, MemoryStream, SeekStream};
+use image::{RgbaImage, ImageBuffer, GenericImage, GenericImageView};
+use indexmap::IndexMap;
+
+use crate::error::PackerError;
+
+use super::{
+    PackerAtlas, TextureData, Animation, AnimationFrame, AnimationDirection, Rect, Point, Slice,
+    default_extensions, visit_folder, PathFilter
+};
+use super::output::{
+    BINARY_MAGIC, BINARY_VERSION, KNOWN_FLAGS,
+    FLAG_BIG_ENDIAN, FLAG_16BIT_COORDINATES, FLAG_NULL_TERMINATED_STRINGS
+};
+
+/// Reads an atlas written by any of the outputs, except templates, with the
+/// format matching the extension of the path. For the `Godot` output, the
+/// path is the folder of the frames or the `SpriteFrames` resource next to it.
+pub fn read_atlas(path: &Path) -> anyhow::Result<PackerAtlas> {
+    if path.is_dir() {
+        return read_godot(path);
+    }
+    let extension = path
+        .extension()
+        .and_then(std::ffi::OsStr::to_str)
+        .unwrap_or_default();
+    let atlas = match extension {
+        "json" => serde_json::from_slice(&std::fs::read(path)?)?,
+        "ron" => ron::from_str(&std::fs::read_to_string(path)?)?,
+        "toml" => toml::from_str(&std::fs::read_to_string(path)?)?,
+        "bin" => read_binary(std::fs::read(path)?)?,
+        "tres" => read_godot(&path.with_extension(""))?,
+        _ => Err(PackerError::UnsupportedAtlasFormat)?
+    };
+    Ok(atlas)
+}
+
+/// The frames written by `unpack`.
+#[derive(Default)]
+pub struct Unpacked {
+    /// The path of every written frame.
+    pub frames: Vec<PathBuf>,
+    /// The frames that were not written, with the reason.
+    pub skipped: Vec<(String, String)>
+}
+
+/// Writes every frame of the atlas as an image named after the frame inside
+/// the output folder, reversing the rotation and the trimming.
+pub fn unpack(atlas_path: &Path, output_path: &Path) -> anyhow::Result<Unpacked> {
+    let mut atlas = read_atlas(atlas_path)?;
+    if atlas.pages.is_empty() {
+        atlas.pages.push(atlas.sheet_path.clone());
+    }
+
+    let sheets = atlas.pages.iter()
+        .map(|page| {
+            let page = find_page(atlas_path, page);
+            image::open(&page)
+                .map(|img| img.to_rgba8())
+                .map_err(|err| anyhow::anyhow!("{}: {}", page.display(), err))
+        })
+        .collect::<anyhow::Result<Vec<RgbaImage>>>()?;
+
+    let mut unpacked = Unpacked::default();
+    for (name, data) in atlas.frames.iter() {
+        let Some(path) = frame_path(output_path, name) else {
+            unpacked.skipped.push((name.clone(), "invalid name".into()));
+            continue;
+        };
+        let Some(sheet) = sheets.get(data.page) else {
+            unpacked.skipped.push((name.clone(), format!("missing page {}", data.page)));
+            continue;
+        };
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        frame_image(sheet, data)?.save_with_format(&path, image::ImageFormat::Png)?;
+        unpacked.frames.push(path);
+    }
+    Ok(unpacked)
+}
+
+/// The pages are relative to where the packer ran, so fall back to the
+/// folder of the atlas when they cannot be found.
+fn find_page(atlas_path: &Path, page: &Path) -> PathBuf {
+    if page.is_file() {
+        return page.to_path_buf();
+    }
+    let folder = atlas_path.parent().unwrap_or(Path::new(""));
+    match page.file_name() {
+        Some(file_name) => folder.join(file_name),
+        None => page.to_path_buf()
+    }
+}
+
+/// The path of the image of a frame, or `None` if the name would leave the output folder.
+fn frame_path(output_path: &Path, name: &str) -> Option<PathBuf> {
+    let name = Path::new(name);
+    if name.components().any(|component| !matches!(component, Component::Normal(_))) {
+        return None;
+    }
+    let path = output_path.join(name);
+    let known_extension = name
+        .extension()
+        .and_then(std::ffi::OsStr::to_str)
+        .is_some_and(|ext| default_extensions().iter().any(|known| known.eq_ignore_ascii_case(ext)));
+    if known_extension {
+        Some(path.with_extension("png"))
+    } else {
+        let mut path = path.into_os_string();
+        path.push(".png");
+        Some(path.into())
+    }
+}
+
+/// Cuts the frame out of the sheet and puts it back at its place in the original image.
+fn frame_image(sheet: &RgbaImage, data: &TextureData) -> anyhow::Result<RgbaImage> {
+    let right = data.x.checked_add(data.width).filter(|&right| right <= sheet.width());
+    let bottom = data.y.checked_add(data.height).filter(|&bottom| bottom <= sheet.height());
+    if right.is_none() || bottom.is_none() {
+        anyhow::bail!("The frame is outside of the sheet");
+    }
+    let mut img = sheet.view(data.x, data.y, data.width, data.height).to_image();
+    // The packer rotates the images clockwise.
+    if data.rotated {
+        img = image::imageops::rotate270(&img);
+    }
+
+    let width = data.source_width.max(data.offset_x.saturating_add(img.width()));
+    let height = data.source_height.max(data.offset_y.saturating_add(img.height()));
+    if (width, height) == img.dimensions() {
+        return Ok(img);
+    }
+    let mut source: RgbaImage = ImageBuffer::from_fn(width, height, |_, _| image::Rgba([0, 0, 0, 0]));
+    source.copy_from(&img, data.offset_x, data.offset_y)?;
+    Ok(source)
+}
+
+/// Reads the values written by the `Binary` output, with the layout given by its flags.
+struct AtlasReader<'a> {
+    reader: BinaryReader<'a>,
+    flags: u32
+}
+
+impl<'a> AtlasReader<'a> {
+    fn new(stream: &'a mut MemoryStream, flags: u32) -> Self {
+        let endian = if flags & FLAG_BIG_ENDIAN != 0 { Endian::Big } else { Endian::Little };
+        AtlasReader { reader: BinaryReader::new(stream, endian), flags }
+    }
+
+    fn read_string(&mut self) -> anyhow::Result<String> {
+        let bytes = if self.flags & FLAG_NULL_TERMINATED_STRINGS != 0 {
+            let mut bytes = vec![];
+            loop {
+                match self.reader.read_u8()? {
+                    0 => break,
+                    byte => bytes.push(byte)
+                }
+            }
+            bytes
+        } else {
+            let length = self.read_7bit_encoded_int()?;
+            self.read_bytes(length as usize)?
+        };
+        Ok(String::from_utf8(bytes)?)
+    }
+
+    /// Reads `length` bytes. The length comes from the file, so it is checked
+    /// against what is left before allocating anything for it.
+    fn read_bytes(&mut self, length: usize) -> anyhow::Result<Vec<u8>> {
+        let remaining = self.reader.len()?.saturating_sub(self.reader.tell()?);
+        if length > remaining {
+            Err(PackerError::InvalidBinaryAtlas)?
+        }
+        Ok(self.reader.read_bytes(length)?)
+    }
+
+    fn read_7bit_encoded_int(&mut self) -> anyhow::Result<u32> {
+        let mut value = 0u32;
+        for shift in (0..35).step_by(7) {
+            let byte = self.reader.read_u8()?;
+            value |= ((byte & 0x7F) as u32) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(PackerError::InvalidBinaryAtlas)?
+    }
+
+    fn read_coordinate(&mut self) -> anyhow::Result<u32> {
+        if self.flags & FLAG_16BIT_COORDINATES != 0 {
+            Ok(self.reader.read_u16()? as u32)
+        } else {
+            Ok(self.reader.read_u32()?)
+        }
+    }
+
+    fn read_signed_coordinate(&mut self) -> anyhow::Result<i32> {
+        if self.flags & FLAG_16BIT_COORDINATES != 0 {
+            Ok(self.reader.read_i16()? as i32)
+        } else {
+            Ok(self.reader.read_i32()?)
+        }
+    }
+
+    fn read_u32(&mut self) -> anyhow::Result<u32> {
+        Ok(self.reader.read_u32()?)
+    }
+
+    fn read_bool(&mut self) -> anyhow::Result<bool> {
+        Ok(self.reader.read_bool()?)
+    }
+
+    fn read_nine_patch(&mut self) -> anyhow::Result<Option<Rect>> {
+        if !self.read_bool()? {
+            return Ok(None);
+        }
+        Ok(Some(Rect {
+            x: self.read_coordinate()?,
+            y: self.read_coordinate()?,
+            w: self.read_coordinate()?,
+            h: self.read_coordinate()?
+        }))
+    }
+
+    fn read_pivot(&mut self) -> anyhow::Result<Option<Point>> {
+        if !self.read_bool()? {
+            return Ok(None);
+        }
+        Ok(Some(Point { x: self.read_signed_coordinate()?, y: self.read_signed_coordinate()? }))
+    }
+
+    fn read_frame(&mut self) -> anyhow::Result<(String, TextureData)> {
+        let name = self.read_string()?;
+        let (x, y) = (self.read_coordinate()?, self.read_coordinate()?);
+        let (width, height) = (self.read_coordinate()?, self.read_coordinate()?);
+        let page = self.read_u32()? as usize;
+        let (source_width, source_height) = (self.read_coordinate()?, self.read_coordinate()?);
+        let (offset_x, offset_y) = (self.read_coordinate()?, self.read_coordinate()?);
+        let rotated = self.read_bool()?;
+        let alias_of = Some(self.read_string()?).filter(|alias| !alias.is_empty());
+        let nine_patch = self.read_nine_patch()?;
+        let pivot = self.read_pivot()?;
+        let mut slices = vec![];
+        for _ in 0..self.read_u32()? {
+            slices.push(Slice {
+                name: self.read_string()?,
+                x: self.read_signed_coordinate()?,
+                y: self.read_signed_coordinate()?,
+                w: self.read_coordinate()?,
+                h: self.read_coordinate()?,
+                nine_patch: self.read_nine_patch()?,
+                pivot: self.read_pivot()?
+            });
+        }
+        Ok((name, TextureData {
+            page, x, y, width, height,
+            source_width, source_height,
+            offset_x, offset_y,
+            rotated, nine_patch, pivot, slices, alias_of
+        }))
+    }
+}
+
+fn read_binary(bytes: Vec<u8>) -> anyhow::Result<PackerAtlas> {
+    let mut stream = MemoryStream::from(bytes);
+    let mut header = BinaryReader::new(&mut stream, Endian::Little);
+    if header.read_bytes(BINARY_MAGIC.len())? != BINARY_MAGIC || header.read_u16()? != BINARY_VERSION {
+        Err(PackerError::InvalidBinaryAtlas)?
+    }
+    let flags = header.read_u32()?;
+    if flags & !KNOWN_FLAGS != 0 {
+        Err(PackerError::InvalidBinaryAtlas)?
+    }
+
+    let mut reader = AtlasReader::new(&mut stream, flags);
+    let sheet_path = PathBuf::from(reader.read_string()?);
+    let mut pages = vec![];
+    for _ in 0..reader.read_u32()? {
+        pages.push(PathBuf::from(reader.read_string()?));
+    }
+
+    let mut frames = IndexMap::new();
+    for _ in 0..reader.read_u32()? {
+        // Only read the fields that are known, and skip the rest of the frame.
+        let size = reader.read_u32()?;
+        let mut record = MemoryStream::from(reader.read_bytes(size as usize)?);
+        let (name, data) = AtlasReader::new(&mut record, flags).read_frame()?;
+        frames.insert(name, data);
+    }
+
+    let mut animations = vec![];
+    for _ in 0..reader.read_u32()? {
+        let name = reader.read_string()?;
+        let direction = match reader.reader.read_u8()? {
+            1 => AnimationDirection::Reverse,
+            2 => AnimationDirection::PingPong,
+            _ => AnimationDirection::Forward
+        };
+        let mut frames = vec![];
+        for _ in 0..reader.read_u32()? {
+            frames.push(AnimationFrame { name: reader.read_string()?, duration: reader.read_u32()? });
+        }
+        animations.push(Animation { name, direction, frames });
+    }
+
+    Ok(PackerAtlas { sheet_path, pages, frames, animations })
+}
+
+/// Reads the `AtlasTexture` resources inside the folder of the frames.
+fn read_godot(folder: &Path) -> anyhow::Result<PackerAtlas> {
+    let mut resources = vec![];
+    visit_folder(folder.to_path_buf(), &PathFilter::new(&["*.tres".into()], &[])?, &mut resources)?;
+
+    let mut atlas = PackerAtlas::default();
+    for resource in resources {
+        let content = std::fs::read_to_string(&resource)?;
+        let name = resource
+            .strip_prefix(folder)?
+            .with_extension("")
+            .to_str()
+            .unwrap_or_default()
+            .replace('\\', "/");
+
+        let Some(sheet) = content.lines().find_map(|line| {
+            let line = line.strip_prefix("[ext_resource type=\"Texture2D\" path=\"")?;
+            line.split('"').next()
+        }) else { continue };
+        let sheet = resource.parent().unwrap_or(folder).join(sheet);
+        let page = match atlas.pages.iter().position(|page| page == &sheet) {
+            Some(page) => page,
+            None => {
+                atlas.add_page(&sheet);
+                atlas.pages.len() - 1
+            }
+        };
+
+        let rect2 = |key: &str| content.lines().find_map(|line| {
+            let values = line.strip_prefix(key)?.trim().strip_prefix("= Rect2(")?.strip_suffix(')')?;
+            let values: Vec<u32> = values.split(',').filter_map(|value| value.trim().parse().ok()).collect();
+            <[u32; 4]>::try_from(values).ok()
+        });
+        let Some([x, y, width, height]) = rect2("region") else { continue };
+        let [offset_x, offset_y, margin_width, margin_height] = rect2("margin").unwrap_or_default();
+
+        atlas.add(&name, TextureData {
+            page, x, y, width, height,
+            source_width: width + margin_width,
+            source_height: height + margin_height,
+            offset_x, offset_y,
+            rotated: false,
+            nine_patch: None,
+            pivot: None,
+            slices: vec![],
+            alias_of: None
+        });
+    }
+    atlas.sort_frames(Default::default());
+    Ok(atlas)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::atlas::{
+        pack_images, save, Config, OutputType, ImageOptions, ImageTexture, SizeMode, BinaryOptions,
+        Endianness, CoordinateWidth, StringEncoding, Features
+    };
+
+    /// A pattern that differs between the images, so a frame cut from the
+    /// wrong place does not match.
+    fn pattern(seed: u8, width: u32, height: u32) -> RgbaImage {
+        ImageBuffer::from_fn(width, height, |x, y| {
+            image::Rgba([seed.wrapping_mul(31), (x * 7) as u8, (y * 13) as u8, 255])
+        })
+    }
+
+    /// The images with their name, including a wide one that only fits
+    /// rotated, a trimmed one and a duplicate.
+    fn sources() -> Vec<(String, RgbaImage)> {
+        let mut trimmed: RgbaImage = ImageBuffer::from_fn(16, 16, |_, _| image::Rgba([0, 0, 0, 0]));
+        trimmed.copy_from(&pattern(1, 6, 10), 3, 2).unwrap();
+        let mut sources = vec![
+            ("wide".to_owned(), pattern(2, 40, 6)),
+            ("trimmed".to_owned(), trimmed),
+            ("solid".to_owned(), pattern(3, 8, 8)),
+            ("folder/copy".to_owned(), pattern(3, 8, 8))
+        ];
+        for i in 0..4 {
+            sources.push((format!("block{i}"), pattern(10 + i, 12, 12)));
+        }
+        sources
+    }
+
+    fn image_options(allow_rotation: bool) -> ImageOptions {
+        // Too small for a single page, and too narrow for the wide image
+        // unless it is rotated.
+        let size_mode = if allow_rotation {
+            SizeMode::Fixed { width: 16, height: 48 }
+        } else {
+            SizeMode::Fixed { width: 48, height: 16 }
+        };
+        ImageOptions {
+            size_mode,
+            trim: true,
+            allow_rotation,
+            padding: 1,
+            remove_duplicates: true,
+            show_aliases: true,
+            ..Default::default()
+        }
+    }
+
+    fn animations() -> Vec<Animation> {
+        vec![Animation {
+            name: "blocks".into(),
+            direction: AnimationDirection::PingPong,
+            frames: (0..4).map(|i| AnimationFrame { name: format!("block{i}"), duration: 100 }).collect()
+        }]
+    }
+
+    /// Packs the images, writes them with the output and reads them back,
+    /// returning the packed and the read atlas.
+    fn round_trip(
+        output_type: OutputType,
+        binary_options: BinaryOptions,
+        allow_rotation: bool
+    ) -> (PackerAtlas, PackerAtlas) {
+        let output_path = std::env::temp_dir().join(format!(
+            "cluttered-round-trip-{}-{}", std::process::id(), folder_name(&output_type, &binary_options, allow_rotation)));
+        let config = Config {
+            name: "atlas".into(),
+            output_path: output_path.clone(),
+            folders: vec![],
+            extensions: default_extensions(),
+            allow_normal_output: true,
+            template_path: None,
+            strict: false,
+            output_type,
+            image_options: image_options(allow_rotation),
+            binary_options,
+            features: Features::default(),
+            extended_from: vec![]
+        };
+        let images = || sources()
+            .into_iter()
+            .map(|(name, img)| ImageTexture::new(name, img, None))
+            .collect();
+
+        let mut packed = pack_images(images(), animations(), &config.image_options).unwrap();
+        for page in 0..packed.sheets.len() {
+            packed.atlas.add_page(&output_path.join(format!("atlas_{page}.png")));
+        }
+        let expected = packed.atlas.clone();
+        save(&config, &None, pack_images(images(), animations(), &config.image_options).unwrap()).unwrap();
+
+        let atlas_path = match config.output_type {
+            OutputType::Json => output_path.join("atlas.json"),
+            OutputType::Binary => output_path.join("atlas.bin"),
+            OutputType::Ron => output_path.join("atlas.ron"),
+            OutputType::Toml => output_path.join("atlas.toml"),
+            OutputType::Godot => output_path.join("atlas")
+        };
+        let read = read_atlas(&atlas_path).unwrap();
+        check_frames(&atlas_path, &read);
+        std::fs::remove_dir_all(output_path).unwrap();
+        (expected, read)
+    }
+
+    /// A name for the output folder of every combination.
+    fn folder_name(output_type: &OutputType, options: &BinaryOptions, allow_rotation: bool) -> String {
+        serde_json::to_string(&(output_type, options, allow_rotation))
+            .unwrap()
+            .replace(|c: char| !c.is_ascii_alphanumeric(), "")
+    }
+
+    /// Checks that every frame of the read atlas unpacks to its source image.
+    fn check_frames(atlas_path: &Path, atlas: &PackerAtlas) {
+        let sheets: Vec<RgbaImage> = atlas.pages
+            .iter()
+            .map(|page| image::open(find_page(atlas_path, page)).unwrap().to_rgba8())
+            .collect();
+        for (name, img) in sources() {
+            let data = &atlas.frames[&name];
+            assert_eq!(frame_image(&sheets[data.page], data).unwrap(), img, "{name}");
+        }
+    }
+
+    /// Checks that the packed atlas has every case the outputs should keep.
+    fn check_coverage(atlas: &PackerAtlas) {
+        let frames = || atlas.frames.values();
+        assert!(atlas.pages.len() > 1, "single page");
+        assert!(frames().any(|data| data.offset_x > 0 && data.offset_y > 0), "nothing trimmed");
+        assert!(frames().any(|data| data.alias_of.is_some()), "no alias");
+        if atlas.frames["wide"].width == 6 {
+            assert!(atlas.frames["wide"].rotated);
+        }
+    }
+
+    #[test]
+    fn every_output_reads_back_the_same_atlas() {
+        for output_type in [OutputType::Json, OutputType::Ron, OutputType::Toml] {
+            for allow_rotation in [false, true] {
+                let (expected, read) = round_trip(output_type.clone(), BinaryOptions::default(), allow_rotation);
+                check_coverage(&expected);
+                assert_eq!(read.frames, expected.frames);
+                assert_eq!(read.pages, expected.pages);
+                assert_eq!(read.animations, expected.animations);
+            }
+        }
+    }
+
+    #[test]
+    fn every_binary_layout_reads_back_the_same_atlas() {
+        for endianness in [Endianness::Little, Endianness::Big] {
+            for coordinate_width in [CoordinateWidth::U32, CoordinateWidth::U16] {
+                for string_encoding in [StringEncoding::LengthPrefixed, StringEncoding::NullTerminated] {
+                    let options = BinaryOptions { endianness, coordinate_width, string_encoding };
+                    let (expected, read) = round_trip(OutputType::Binary, options, true);
+                    check_coverage(&expected);
+                    assert!(expected.frames["wide"].rotated);
+                    assert_eq!(read.frames, expected.frames);
+                    assert_eq!(read.pages, expected.pages);
+                    assert_eq!(read.animations, expected.animations);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn godot_output_reads_back_the_regions() {
+        let (expected, read) = round_trip(OutputType::Godot, BinaryOptions::default(), false);
+        check_coverage(&expected);
+        // AtlasTexture only keeps the region and the margin of the frames.
+        for (name, data) in expected.frames.iter() {
+            let region = |data: &TextureData| (
+                data.x, data.y, data.width, data.height,
+                data.source_width, data.source_height, data.offset_x, data.offset_y
+            );
+            assert_eq!(region(&read.frames[name]), region(data), "{name}");
+        }
+    }
+
+    #[test]
+    fn godot_output_keeps_the_resources_inside_the_atlas_folder() {
+        let output_path = std::env::temp_dir().join(format!("cluttered-godot-paths-{}", std::process::id()));
+        let config = Config {
+            name: "atlas".into(),
+            output_path: output_path.join("out"),
+            folders: vec![],
+            extensions: default_extensions(),
+            allow_normal_output: true,
+            template_path: None,
+            strict: false,
+            output_type: OutputType::Godot,
+            image_options: ImageOptions::default(),
+            binary_options: BinaryOptions::default(),
+            features: Features::default(),
+            extended_from: vec![]
+        };
+        let images = vec![ImageTexture::new("../../art/gem".into(), pattern(1, 8, 8), None)];
+        save(&config, &None, pack_images(images, vec![], &config.image_options).unwrap()).unwrap();
+
+        assert!(output_path.join("out/atlas/art/gem.tres").is_file());
+        assert!(!output_path.join("art").exists());
+        // The resource still points at the sheet from where it was written.
+        let read = read_atlas(&output_path.join("out/atlas")).unwrap();
+        let sheet = output_path.join("out/atlas.png");
+        assert_eq!(read.pages[0].canonicalize().unwrap(), sheet.canonicalize().unwrap());
+        let sheet = image::open(sheet).unwrap().to_rgba8();
+        assert_eq!(frame_image(&sheet, &read.frames["art/gem"]).unwrap(), pattern(1, 8, 8));
+        std::fs::remove_dir_all(output_path).unwrap();
+    }
+
+    #[test]
+    fn refuses_lengths_longer_than_the_file() {
+        let mut bytes = BINARY_MAGIC.to_vec();
+        bytes.extend(BINARY_VERSION.to_le_bytes());
+        bytes.extend(0u32.to_le_bytes());
+        // A sheet path claiming to be about 2 GB long.
+        bytes.extend([0xFF, 0xFF, 0xFF, 0xFF, 0x07]);
+        let err = read_binary(bytes).unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(PackerError::InvalidBinaryAtlas)));
+    }
+
+    #[test]
+    fn refuses_unknown_flags() {
+        let mut bytes = BINARY_MAGIC.to_vec();
+        bytes.extend(BINARY_VERSION.to_le_bytes());
+        bytes.extend((1u32 << 31).to_le_bytes());
+        let err = read_binary(bytes).unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(PackerError::InvalidBinaryAtlas)));
+    }
+}
@@ -2,4 +2,35 @@
 pub enum PackerError {
     #[error("Failed to packed an image.")]
     FailedToPacked,
+    #[error("The image {name} ({width}x{height}) does not fit into an empty sheet.")]
+    ImageTooLarge { name: String, width: u32, height: u32 },
+    #[error("Failed to read {} image(s), aborting because of strict mode:{}", .0.len(), list_files(.0))]
+    UnreadableImages(Vec<(std::path::PathBuf, String)>),
+    #[error("Unsupported format. Supported Format: .ron, .json, .toml")]
+    UnsupportedFormat,
+    #[error("Failed to pack {0} atlas(es).")]
+    FailedAtlases(usize),
+    #[error("Found {0} error(s) in the config.")]
+    InvalidConfigCheck(usize),
+    #[error("The folder {} does not exist.", .0.display())]
+    MissingFolder(std::path::PathBuf),
+    #[error("Invalid config: {0}")]
+    InvalidConfig(String),
+    #[error("{}:{}:{}: {}", .file.display(), .line, .column, .message)]
+    ConfigSyntax { file: std::path::PathBuf, line: usize, column: usize, message: String },
+    #[error("Unsupported atlas format. Supported Format: .json, .ron, .toml, .bin, .tres")]
+    UnsupportedAtlasFormat,
+    #[error("Not a cluttered binary atlas, or a version of it that cannot be read.")]
+    InvalidBinaryAtlas,
+    #[error("{0} does not fit into a 16-bit coordinate, use 32-bit coordinates instead.")]
+    CoordinateOutOfRange(i64),
+    #[error("The Godot output cannot show rotated frames, set allow_rotation = false.")]
+    RotatedGodotFrames,
+    #[error("Several frames would be written to the Godot resource {}.", .0.display())]
+    DuplicateGodotResource(std::path::PathBuf),
+}
+
+/// One `path: reason` line per file, each on its own line.
+fn list_files(files: &[(std::path::PathBuf, String)]) -> String {
+    files.iter().map(|(file, reason)| format!("\n  {}: {}", file.display(), reason)).collect()
 }
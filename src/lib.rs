@@ -0,0 +1,17 @@
+//! A texture packer that packs a bunch of images into sheets based on
+//! [crunch-rs](https://github.com/ChevyRay/crunch-rs).
+//!
+//! ```no_run
+//! use std::path::PathBuf;
+//! use cluttered::atlas::{self, Config};
+//!
+//! let input_path = PathBuf::from("packer-config.toml");
+//! let config = Config::from_path(&input_path)?;
+//! let packed = atlas::pack_config(&config, &Some(input_path))?;
+//! for (name, frame) in packed.atlas.frames.iter() {
+//!     println!("{name}: {}x{} on page {}", frame.width, frame.height, frame.page);
+//! }
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+pub mod atlas;
+pub mod error;
@@ -1,8 +1,10 @@
-mod atlas;
-mod error;
+mod watch;
 use std::path::PathBuf;
 
-use atlas::{Config, ImageOptions, Features, TemplatePath};
+use cluttered::atlas::{
+    self, Config, ImageOptions, Features, TemplatePath, Folder, BinaryOptions, Severity, PackStatus, PackReport
+};
+use cluttered::error::PackerError;
 
 use thiserror::Error;
 use clap::{Command, Arg, ArgMatches};
@@ -15,39 +17,37 @@ enum CommandError {
     CommandNotFound,
     #[error("Missing one argument, please use --help")]
     MissingOneArgument,
-    #[error("Unsupported format. Supported Format: .ron, .json, .toml")]
-    UnsupportedFormat
 }
 
 fn main() -> anyhow::Result<()> {
     let matches = cli().get_matches();
     match matches.subcommand() {
         Some(("config", sub_matches)) => {
+            set_jobs(sub_matches)?;
             let input_path = get_path("input", sub_matches)?;
-            let extension = input_path.extension();
-            if let Some(extension) = extension {
-                let extension = extension.to_str().unwrap_or_default();
-                match extension {
-                    "ron" => {
-                        let config = Config::from_ron(&input_path)?;
-                        atlas::pack(config, Some(input_path))?;
-                    }
-                    "json" => {
-                        let config = Config::from_json(&input_path)?;
-                        atlas::pack(config, Some(input_path))?;
-                    }
-                    "toml" => {
-                        let config = Config::from_toml(&input_path)?;
-                        atlas::pack(config, Some(input_path))?;
-                    }
-                    _ => Err(CommandError::UnsupportedFormat)?
+            let strict = sub_matches.get_flag("strict");
+            let force = sub_matches.get_flag("force");
+            if sub_matches.get_flag("watch") {
+                watch::watch(input_path, strict, force)?;
+            } else {
+                let mut configs = Config::all_from_path(&input_path)?;
+                for config in configs.iter_mut() {
+                    config.strict |= strict;
                 }
+                report_packs(atlas::pack_all(configs, Some(input_path), force))?;
             }
         },
         Some(("pack", sub_matches)) => {
+            set_jobs(sub_matches)?;
             if let Some(paths) = sub_matches.get_many::<PathBuf>("input") {
-                let folders: Vec<PathBuf> = paths.map(|x| {
-                    x.to_owned()
+                let include = get_patterns("include", sub_matches);
+                let exclude = get_patterns("exclude", sub_matches);
+                let folders: Vec<Folder> = paths.map(|x| {
+                    Folder::Filtered {
+                        path: x.to_owned(),
+                        include: include.clone(),
+                        exclude: exclude.clone()
+                    }
                 }).collect();
                 let output_path = get_path("output", sub_matches)?;
                 let name = if let Some(name) = sub_matches.get_one::<String>("name") {
@@ -70,19 +70,123 @@ fn main() -> anyhow::Result<()> {
                     output_type,
                     allow_normal_output: true,
                     template_path,
+                    strict: sub_matches.get_flag("strict"),
                     folders,
+                    extensions: atlas::default_extensions(),
                     image_options: ImageOptions::default(),
+                    binary_options: BinaryOptions::default(),
                     features: Features::default(),
+                    extended_from: vec![],
                 };
-                atlas::pack(config, None)?;
+                let name = config.name.clone();
+                let report = atlas::pack(config, None)?;
+                print_report(&name, &report);
             }
 
         }
+        Some(("unpack", sub_matches)) => {
+            let input_path = get_path("input", sub_matches)?;
+            let output_path = get_path("output", sub_matches)?;
+            let unpacked = atlas::unpack(&input_path, &output_path)?;
+            for (name, reason) in unpacked.skipped.iter() {
+                println!("Skipped frame {}: {}", name, reason);
+            }
+            println!("Unpacked {} frame(s) into {}.", unpacked.frames.len(), output_path.display());
+        }
+        Some(("check", sub_matches)) => {
+            let input_path = get_path("input", sub_matches)?;
+            let diagnostics = atlas::check(&input_path);
+            for diagnostic in diagnostics.iter() {
+                println!("{}", diagnostic);
+            }
+            let errors = diagnostics.iter().filter(|x| x.severity == Severity::Error).count();
+            if errors > 0 {
+                Err(PackerError::InvalidConfigCheck(errors))?
+            }
+            println!("{} is valid, with {} warning(s).", input_path.display(), diagnostics.len());
+        }
         _ => Err(CommandError::CommandNotFound)?,
     }
     Ok(())
 }
 
+/// Prints what happened to the configs packed by `pack_all`. A single config
+/// fails with its own error, while several end with a summary of all of them.
+fn report_packs(mut results: Vec<(String, anyhow::Result<PackStatus>)>) -> anyhow::Result<()> {
+    if results.len() == 1 {
+        let (name, result) = results.remove(0);
+        print_status(&name, &result?);
+        return Ok(());
+    }
+
+    for (name, result) in results.iter() {
+        match result {
+            Ok(status) => print_status(name, status),
+            Err(err) => eprintln!("Failed to pack {}: {:?}", name, err)
+        }
+    }
+
+    let (mut packed, mut up_to_date, mut failed) = (0, 0, 0);
+    println!("Summary:");
+    for (name, result) in results.iter() {
+        match result {
+            Ok(PackStatus::Packed { frames, pages, .. }) => {
+                packed += 1;
+                println!("  {}: {} frame(s) on {} page(s)", name, frames, pages);
+            }
+            Ok(PackStatus::UpToDate) => {
+                up_to_date += 1;
+                println!("  {}: up to date", name);
+            }
+            Err(err) => {
+                failed += 1;
+                println!("  {}: failed, {}", name, err);
+            }
+        }
+    }
+    println!("{} atlas(es): {} packed, {} up to date, {} failed", results.len(), packed, up_to_date, failed);
+
+    if failed > 0 {
+        Err(PackerError::FailedAtlases(failed))?
+    }
+    Ok(())
+}
+
+fn print_status(name: &str, status: &PackStatus) {
+    match status {
+        PackStatus::UpToDate => {
+            println!("Nothing changed in {} since the last pack, skipping. Use --force to pack anyway.", name);
+        }
+        PackStatus::Packed { report, .. } => print_report(name, report)
+    }
+}
+
+fn print_report(name: &str, report: &PackReport) {
+    if !report.skipped.is_empty() {
+        println!("Skipped {} file(s) that could not be read:", report.skipped.len());
+        for (file, reason) in report.skipped.iter() {
+            println!("  {}: {}", file.display(), reason);
+        }
+    }
+    for (file, warning) in report.warnings.iter() {
+        println!("Warning: {}: {}", file.display(), warning);
+    }
+    for (packer, reason) in report.failed_packers.iter() {
+        println!("Packer {}: failed, {}", packer, reason);
+    }
+    for score in report.candidates.iter() {
+        println!(
+            "Packer {}: {} page(s), {} pixels, {:.2}% occupied",
+            score.name, score.pages, score.area, score.occupancy
+        );
+    }
+    let packer = &report.packer;
+    println!(
+        "Packed {} with {}: {} page(s), {:.2}% occupied.",
+        name, packer.name, packer.pages, packer.occupancy
+    );
+}
+
 #[inline]
 fn get_path(id: &str, matches: &ArgMatches) -> anyhow::Result<PathBuf, CommandError> {
     match matches.get_one::<PathBuf>(id) {
@@ -91,6 +195,38 @@ fn get_path(id: &str, matches: &ArgMatches) -> anyhow::Result<PathBuf, CommandEr
     }
 }
 
+fn get_patterns(id: &str, matches: &ArgMatches) -> Vec<String> {
+    matches
+        .get_many::<String>(id)
+        .map(|patterns| patterns.cloned().collect())
+        .unwrap_or_default()
+}
+
+/// Caps the number of threads decoding and processing the images.
+fn set_jobs(matches: &ArgMatches) -> anyhow::Result<()> {
+    if let Some(&jobs) = matches.get_one::<usize>("jobs") {
+        rayon::ThreadPoolBuilder::new().num_threads(jobs).build_global()?;
+    }
+    Ok(())
+}
+
+fn jobs_arg() -> Arg {
+    Arg::new("jobs")
+        .short('j')
+        .long("jobs")
+        .value_parser(clap::value_parser!(usize))
+        .num_args(1)
+        .help("The number of threads used to read the images. Defaults to the number of cores.")
+}
+
+fn strict_arg() -> Arg {
+    Arg::new("strict")
+        .short('s')
+        .long("strict")
+        .action(clap::ArgAction::SetTrue)
+        .help("Fail instead of skipping the images that could not be read.")
+}
+
 fn cli() -> Command {
     Command::new("pack")
         .about("Pack an images")
@@ -105,6 +241,18 @@ fn cli() -> Command {
                      .required(true)
                      .num_args(1)
                      .help("Specify an input for a configuration path to start packing."))
+                .arg(Arg::new("watch")
+                     .short('w')
+                     .long("watch")
+                     .action(clap::ArgAction::SetTrue)
+                     .help("Keep running and repack whenever the images, templates or the config change."))
+                .arg(Arg::new("force")
+                     .short('f')
+                     .long("force")
+                     .action(clap::ArgAction::SetTrue)
+                     .help("Pack even if nothing changed since the last pack."))
+                .arg(jobs_arg())
+                .arg(strict_arg())
        )
         .subcommand(
             Command::new("pack")
@@ -144,5 +292,48 @@ fn cli() -> Command {
                      .required(false)
                      .num_args(1)
                      .help("Specify an output name."))
+                .arg(Arg::new("include")
+                     .value_parser(clap::value_parser!(String))
+                     .long("include")
+                     .required(false)
+                     .num_args(1..)
+                     .help("Only pack the files matching these glob patterns."))
+                .arg(Arg::new("exclude")
+                     .value_parser(clap::value_parser!(String))
+                     .long("exclude")
+                     .required(false)
+                     .num_args(1..)
+                     .help("Skip the files and folders matching these glob patterns."))
+                .arg(jobs_arg())
+                .arg(strict_arg())
+        )
+        .subcommand(
+            Command::new("check")
+                .about("Check a configuration without packing it.")
+                .arg(Arg::new("input")
+                     .short('i')
+                     .value_parser(clap::value_parser!(PathBuf))
+                     .long("input")
+                     .required(true)
+                     .num_args(1)
+                     .help("Specify the configuration path to check."))
+       )
+        .subcommand(
+            Command::new("unpack")
+                .about("Split a packed atlas back into its images.")
+                .arg(Arg::new("input")
+                     .short('i')
+                     .value_parser(clap::value_parser!(PathBuf))
+                     .long("input")
+                     .required(true)
+                     .num_args(1)
+                     .help("Specify the atlas file (.json, .ron, .toml, .bin), or the folder or .tres of the Godot output."))
+                .arg(Arg::new("output")
+                     .short('o')
+                     .value_parser(clap::value_parser!(PathBuf))
+                     .long("output")
+                     .required(true)
+                     .num_args(1)
+                     .help("Specify an output folder path for the images."))
         )
 }
@@ -0,0 +1,93 @@
+use std::{path::PathBuf, sync::mpsc::{self, Receiver}, time::Duration};
+
+use notify_debouncer_mini::{new_debouncer, notify::RecursiveMode, DebounceEventResult};
+
+use cluttered::atlas::{self, Config};
+
+const DEBOUNCE_TIMEOUT: Duration = Duration::from_millis(500);
+
+struct Watched {
+    paths: Vec<PathBuf>,
+    /// The configs, to tell the files they write apart from their inputs.
+    configs: Vec<Config>,
+    input_path: Option<PathBuf>
+}
+
+/// Packs the config, then packs it again every time something it depends on
+/// changed. `force` only applies to the first pack.
+pub fn watch(input_path: PathBuf, strict: bool, force: bool) -> anyhow::Result<()> {
+    let (tx, rx) = mpsc::channel();
+    let mut debouncer = new_debouncer(DEBOUNCE_TIMEOUT, tx)?;
+
+    let mut force = force;
+    loop {
+        let watched = repack(&input_path, strict, force);
+        force = false;
+        for path in watched.paths.iter() {
+            if let Err(err) = debouncer.watcher().watch(path, RecursiveMode::Recursive) {
+                eprintln!("Cannot watch {}: {}", path.display(), err);
+            }
+        }
+
+        println!("Watching for changes...");
+        wait_for_changes(&rx, &watched)?;
+
+        for path in watched.paths.iter() {
+            let _ = debouncer.watcher().unwatch(path);
+        }
+    }
+}
+
+/// Packs the atlases of the config again, returning the paths that should be watched for the next repack.
+fn repack(input_path: &PathBuf, strict: bool, force: bool) -> Watched {
+    let mut configs = match Config::all_from_path(input_path) {
+        Ok(configs) => configs,
+        Err(err) => {
+            eprintln!("Failed to load the config {}: {:?}", input_path.display(), err);
+            return Watched {
+                paths: vec![input_path.to_owned()],
+                configs: vec![],
+                input_path: None
+            };
+        }
+    };
+
+    let input_path = Some(input_path.to_owned());
+    let mut paths: Vec<PathBuf> = vec![];
+    for config in configs.iter_mut() {
+        config.strict |= strict;
+        for path in config.watched_paths(&input_path) {
+            if path.exists() && !paths.contains(&path) {
+                paths.push(path);
+            }
+        }
+    }
+
+    match crate::report_packs(atlas::pack_all(configs.clone(), input_path.clone(), force)) {
+        Ok(()) => println!("Done."),
+        Err(err) => eprintln!("Failed to repack: {:?}", err)
+    }
+
+    Watched {
+        paths,
+        configs,
+        input_path
+    }
+}
+
+/// Blocks until a file changed that is not written by packing the configs.
+fn wait_for_changes(rx: &Receiver<DebounceEventResult>, watched: &Watched) -> anyhow::Result<()> {
+    loop {
+        match rx.recv()? {
+            Ok(events) => {
+                let changed = events.iter().any(|event| {
+                    !watched.configs.iter().any(|config| config.is_output(&watched.input_path, &event.path))
+                });
+                if changed {
+                    return Ok(());
+                }
+            }
+            Err(err) => eprintln!("Failed to watch the changes: {:?}", err)
+        }
+    }
+}